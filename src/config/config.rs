@@ -1,6 +1,9 @@
-use std::collections::HashMap;
 use std::error::Error;
+use std::fs;
+use regex::Regex;
 use serde_json::Value;
+use crate::config::expr::{self, Expr};
+use crate::storage::connection;
 use crate::config::config::Type::{Boolean, JSON, Number, String as TypeString};
 
 #[derive(Hash, Eq, PartialEq, Debug, Clone)]
@@ -10,102 +13,1280 @@ pub enum Connection {
     MongoDB,
 }
 
+impl Connection {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Connection::PostgresSQL => "postgres",
+            Connection::MySQL => "mysql",
+            Connection::MongoDB => "mongodb",
+        }
+    }
+}
+
 #[derive(PartialEq, Debug)]
 pub enum ExpectedRows {
     Single,
     Multiple
 }
 
-pub enum Type {
-    String,
-    Number,
-    Boolean,
-    JSON,
+impl ExpectedRows {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ExpectedRows::Single => "single",
+            ExpectedRows::Multiple => "multiple",
+        }
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Type {
+    String,
+    Number,
+    Boolean,
+    JSON,
+}
+
+impl Type {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TypeString => "String",
+            Number => "Number",
+            Boolean => "Boolean",
+            JSON => "JSON",
+        }
+    }
+}
+
+/// An attribute of a set evaluated from other attributes of the set instead of a query.
+pub struct Computed {
+    pub name: String,
+    pub ty: Type,
+    pub expr: Expr,
+    /// Fetched attributes the expression reads, directly or through other computed attributes.
+    pub sources: Vec<String>,
+}
+
+pub struct Properties {
+    pub ptype: Type,
+    pub convert_name: Option<String>,
+    pub return_attribute: Option<String>,
+    pub merge: Option<Merge>,
+    /// Transformations applied in order to the fetched value.
+    pub modifiers: Vec<Modifier>,
+    /// Roles that see the value without its masking modifiers, besides `Masking::unmasked`.
+    pub unmask_for: Vec<String>,
+}
+
+impl Properties {
+    pub fn new() -> Self {
+        Self { ptype: TypeString, convert_name: None, return_attribute: None, merge: None, modifiers: vec![], unmask_for: vec![] }
+    }
+}
+
+/// A `!`-modifier of a select attribute, see `domain::transform`.
+#[derive(Debug, Clone)]
+pub enum Modifier {
+    Trim,
+    Lower,
+    Upper,
+    Regex(Regex, String),
+    DateFormat(String),
+    Default(String),
+    Split(String),
+    MaskEmail,
+    MaskLast4,
+    Sha256,
+    Redact,
+}
+
+impl Modifier {
+    /// Masking modifiers are skipped for callers whose role may see the raw value.
+    pub fn masks(&self) -> bool {
+        matches!(self, Modifier::MaskEmail | Modifier::MaskLast4 | Modifier::Sha256 | Modifier::Redact)
+    }
+}
+
+/// Where the caller's role is read from and which roles see unmasked values.
+#[derive(Debug, Clone)]
+pub struct Masking {
+    pub header: String,
+    pub unmasked: Vec<String>,
+    /// Hex SHA-256 digests of the tokens the header may carry and the role each
+    /// one authenticates.
+    pub tokens: Vec<(String, String)>,
+    /// Takes the header as the role itself, for a proxy that authenticates the caller and sets it.
+    pub trust_role_header: bool,
+}
+
+/// Specifiers `!DateFormat` understands after a `%`.
+pub const DATE_SPECIFIERS: &str = "YymdHMS%";
+
+/// How the values of several groups of a set with the same output name are merged.
+#[derive(PartialEq, Debug, Clone)]
+pub enum Merge {
+    FirstNonNull,
+    Prefer(Connection),
+    CollectIntoArray,
+    Error,
+}
+
+/// Classes of backend errors a retry policy can opt into.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum ErrorKind {
+    Deadlock,
+    Connection,
+    PoolExhausted,
+    Other,
+}
+
+impl ErrorKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ErrorKind::Deadlock => "deadlock",
+            ErrorKind::Connection => "connection",
+            ErrorKind::PoolExhausted => "pool_exhausted",
+            ErrorKind::Other => "other",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    pub retry_on: Vec<ErrorKind>,
+}
+
+impl RetryPolicy {
+    pub fn new() -> Self {
+        Self {
+            max_attempts: 1,
+            backoff_ms: 50,
+            max_backoff_ms: 1000,
+            retry_on: vec![ErrorKind::Deadlock, ErrorKind::Connection, ErrorKind::PoolExhausted],
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct BreakerSettings {
+    pub failure_threshold: u32,
+    pub open_ms: u64,
+    pub half_open_probes: u32,
+}
+
+impl BreakerSettings {
+    pub fn new() -> Self {
+        Self { failure_threshold: 5, open_ms: 30_000, half_open_probes: 1 }
+    }
+}
+
+pub struct ConnectionSettings {
+    pub url: Option<String>,
+    pub timeout_ms: Option<u64>,
+    pub retry: Option<RetryPolicy>,
+    pub circuit_breaker: Option<BreakerSettings>,
+}
+
+impl ConnectionSettings {
+    pub fn new() -> Self {
+        Self { url: None, timeout_ms: None, retry: None, circuit_breaker: None }
+    }
+}
+
+/// Where the export reads the ids to fetch from. The query is run page by page,
+/// with `__LIMIT__` and `__OFFSET__` replaced by the page bounds.
+#[derive(Clone, Debug)]
+pub struct ExportSource {
+    pub conn: Connection,
+    pub query: String,
+    pub column: String,
+    pub page_size: usize,
+    pub batch_size: usize,
+    pub concurrency: usize,
+    pub timeout_ms: Option<u64>,
+}
+
+impl ExportSource {
+    pub fn new() -> Self {
+        Self {
+            conn: Connection::PostgresSQL,
+            query: String::default(),
+            column: String::from("id"),
+            page_size: 1000,
+            batch_size: 100,
+            concurrency: 4,
+            timeout_ms: None,
+        }
+    }
+}
+
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Copy)]
+pub enum ParamType {
+    String,
+    Integer,
+    Number,
+    Boolean,
+    /// `YYYY-MM-DD`, bound as text.
+    Date,
+}
+
+impl ParamType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ParamType::String => "string",
+            ParamType::Integer => "integer",
+            ParamType::Number => "number",
+            ParamType::Boolean => "boolean",
+            ParamType::Date => "date",
+        }
+    }
+}
+
+/// Part of the request a named parameter is read from.
+#[derive(PartialEq, Debug, Clone)]
+pub enum ParamSource {
+    Path(String),
+    Query(String),
+    Header(String),
+}
+
+/// A named `:placeholder` available to the queries of an entity.
+#[derive(Debug, Clone)]
+pub struct ParamSpec {
+    pub name: String,
+    pub source: ParamSource,
+    pub ty: ParamType,
+    pub default: Option<String>,
+}
+
+pub struct Config {
+    pub attr_groups: Vec<(String,Vec<AttributeGroup>)>,
+    pub connections: Vec<(Connection, ConnectionSettings)>,
+    pub request_timeout_ms: Option<u64>,
+    pub cache_capacity: usize,
+    /// Most ids a single `POST /ids` call may ask for.
+    pub max_batch_ids: usize,
+    pub export: Option<ExportSource>,
+    pub params: Vec<ParamSpec>,
+    pub masking: Option<Masking>,
+    /// Hex SHA-256 digests of the tokens that may use the admin routes.
+    pub admin_tokens: Vec<String>,
+    /// Computed attributes of every set, each after the computed attributes it reads.
+    pub computed: Vec<(String, Vec<Computed>)>,
+}
+
+impl Config {
+    pub fn new(attr_groups: Vec<(String,Vec<AttributeGroup>)>) -> Self {
+        Self { attr_groups, connections: vec![], request_timeout_ms: None, cache_capacity: 10_000, max_batch_ids: 1000, export: None, params: vec![], masking: None, admin_tokens: vec![], computed: vec![] }
+    }
+
+    pub fn connection(&self, conn: &Connection) -> Option<&ConnectionSettings> {
+        self.connections.iter().find(|(c, _)| c == conn).map(|(_, s)| s)
+    }
+
+    /// Computed attributes of `set`.
+    pub fn computed(&self, set: &str) -> &[Computed] {
+        self.computed.iter().find(|(s, _)| s == set).map(|(_, c)| c.as_slice()).unwrap_or_default()
+    }
+}
+
+/// What a valid id of an entity type looks like.
+#[derive(Debug)]
+pub enum IdRule {
+    Any,
+    Integer,
+    Uuid,
+    Regex(Regex),
+}
+
+impl IdRule {
+    /// `id` in the form the database returns it, such as `42` for `042` or a
+    /// lowercase uuid, so it compares equal to the id column of its rows.
+    /// `None` when it breaks the rule.
+    pub fn canonical(&self, id: &str) -> Option<String> {
+        match self {
+            IdRule::Any => (!id.is_empty()).then(|| id.to_string()),
+            IdRule::Integer => id.parse::<i64>().ok().map(|id| id.to_string()),
+            IdRule::Uuid => uuid::Uuid::parse_str(id).ok().map(|id| id.to_string()),
+            IdRule::Regex(re) => re.is_match(id).then(|| id.to_string()),
+        }
+    }
+}
+
+/// Output `set.attr` of another group, bound to the `:name` placeholder of a group's query.
+#[derive(Debug, Clone)]
+pub struct GroupInput {
+    pub name: String,
+    pub set: String,
+    pub attr: String,
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum JoinKind {
+    Inner,
+    Left,
+}
+
+/// Rows of `left` joined with the rows of `right` whose `right_key` column
+/// equals their `left_key` column.
+pub struct Join {
+    pub left: AttributeGroup,
+    pub right: AttributeGroup,
+    pub left_key: String,
+    pub right_key: String,
+    pub kind: JoinKind,
+}
+
+/// An entity served under its own route prefix.
+pub struct EntityType {
+    pub name: String,
+    pub route: String,
+    pub id_rule: IdRule,
+}
+
+pub struct AttributeGroup {
+    pub conn: Connection,
+    pub query: String,
+    pub exp_rows: ExpectedRows,
+    pub select_attrs: Vec<(String, Properties)>,
+    pub timeout_ms: Option<u64>,
+    pub retry: Option<RetryPolicy>,
+    pub optional: bool,
+    /// Seconds to cache the group's rows for, 0 disables caching.
+    pub cache_ttl: u64,
+    /// Column holding the id in the group's rows, enables batching the group across ids.
+    pub key_column: Option<String>,
+    /// Outputs of other groups the query needs, the group runs after them.
+    pub inputs: Vec<GroupInput>,
+    /// Attribute the rows of a group with children are returned under.
+    pub name: Option<String>,
+    /// Groups run once per row of this group, with the row's columns as placeholders.
+    pub children: Vec<AttributeGroup>,
+    /// Column of the parent rows a child's `key_column` matches, defaults to the `key_column`.
+    pub parent_column: Option<String>,
+    /// Source groups whose rows are joined into the rows of this group, which has no query of its own.
+    pub join: Option<Box<Join>>,
+}
+
+impl AttributeGroup {
+    pub fn new() -> Self {
+        Self {
+            conn: Connection::PostgresSQL,
+            query: String::default(),
+            exp_rows: ExpectedRows::Single,
+            select_attrs: vec![],
+            timeout_ms: None,
+            retry: None,
+            optional: false,
+            cache_ttl: 0,
+            key_column: None,
+            inputs: vec![],
+            name: None,
+            children: vec![],
+            parent_column: None,
+            join: None,
+        }
+    }
+
+    /// Whether an output of the group or of its children has a masking modifier.
+    pub fn masks(&self) -> bool {
+        self.select_attrs.iter().any(|(_, p)| p.modifiers.iter().any(Modifier::masks)) || self.children.iter().any(AttributeGroup::masks)
+    }
+
+    /// Connections the group and the groups it runs query.
+    pub fn connections(&self) -> Vec<&Connection> {
+        let mut conns = match &self.join {
+            Some(join) => vec![&join.left.conn, &join.right.conn],
+            None => vec![&self.conn],
+        };
+        conns.extend(self.children.iter().map(|c| &c.conn));
+        conns
+    }
+
+    /// Names of the attributes the group outputs, after `!ConvertName`.
+    pub fn outputs(&self) -> Vec<&str> {
+        match &self.name {
+            Some(name) if !self.children.is_empty() => vec![name],
+            _ => self.select_attrs.iter().map(|(k, p)| p.convert_name.as_deref().unwrap_or(k)).collect(),
+        }
+    }
+}
+
+pub fn parse(data: &[u8]) -> Result<Config, Box<dyn Error>> {
+    let raw: serde_json::Map<String, Value> = serde_json::from_slice(data)?;
+    parse_map(&raw)
+}
+
+pub fn load_entities(path: &str) -> Result<Vec<(EntityType, Config)>, Box<dyn Error>> {
+    let data = fs::read(path)?;
+    parse_entities(data.as_slice())
+}
+
+/// Parses a config with an `entities` array into every entity type and its own
+/// config. A config without one is a single entity served under `/id` that
+/// accepts any id.
+pub fn parse_entities(data: &[u8]) -> Result<Vec<(EntityType, Config)>, Box<dyn Error>> {
+    let mut raw: serde_json::Map<String, Value> = serde_json::from_slice(data)?;
+
+    let entities = match raw.remove("entities") {
+        None => {
+            let entity = EntityType { name: String::from("entity"), route: String::from("/id"), id_rule: IdRule::Any };
+            let cfg = parse(data)?;
+            check_path_params(&entity, &cfg)?;
+            return Ok(vec![(entity, cfg)])
+        }
+        Some(Value::Array(entities)) => entities,
+        Some(_) => return Err("entities is not an array".into()),
+    };
+
+    if let Some(k) = raw.keys().find(|k| !is_reserved(k)) {
+        return Err(format!("attribute set {} must be inside an entity", k).into())
+    }
+
+    let mut res: Vec<(EntityType, Config)> = vec![];
+    for v in entities.iter() {
+        let (entity, cfg) = parse_entity(&raw, v)?;
+        check_path_params(&entity, &cfg)?;
+        if res.iter().any(|(e, _)| e.name == entity.name || e.route == entity.route) {
+            return Err(format!("duplicate entity {} or route {}", entity.name, entity.route).into())
+        }
+        res.push((entity, cfg));
+    }
+
+    if res.is_empty() {
+        return Err("no entities".into())
+    }
+
+    Ok(res)
+}
+
+fn is_reserved(key: &str) -> bool {
+    matches!(key, "connections" | "request_timeout_ms" | "cache" | "max_batch_ids" | "export" | "params" | "masking" | "admin_tokens")
+}
+
+/// Path params have to name a `{segment}` of the entity's route.
+fn check_path_params(entity: &EntityType, cfg: &Config) -> Result<(), Box<dyn Error>> {
+    for p in cfg.params.iter() {
+        if let ParamSource::Path(seg) = &p.source {
+            if !entity.route.contains(&format!("{{{}}}", seg)) {
+                return Err(format!("param {} reads path segment {} that route {} does not have", p.name, seg, entity.route).into())
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Parses one entity. Its `sets` are parsed together with the top-level
+/// settings in `globals`, which it may override except for `connections`.
+fn parse_entity(globals: &serde_json::Map<String, Value>, value: &Value) -> Result<(EntityType, Config), Box<dyn Error>> {
+    let obj = match value {
+        Value::Object(obj) => obj,
+        _ => return Err("entity is not an object".into())
+    };
+
+    let mut name = None;
+    let mut route = None;
+    let mut id_rule = IdRule::Any;
+    let mut merged = globals.clone();
+
+    for (k, v) in obj {
+        match k.as_str() {
+            "name" => name = Some(parse_string(v)?),
+            "route" => route = Some(parse_string(v)?),
+            "id" => id_rule = parse_id_rule(v)?,
+            "sets" => match v {
+                Value::Object(sets) => {
+                    for (k, v) in sets {
+                        if is_reserved(k) {
+                            return Err(format!("{} is not a valid attribute set name", k).into())
+                        }
+                        merged.insert(k.clone(), v.clone());
+                    }
+                }
+                _ => return Err("sets is not an object".into())
+            },
+            "request_timeout_ms" | "cache" | "max_batch_ids" | "export" | "params" | "masking" => {
+                merged.insert(k.clone(), v.clone());
+            }
+            _ => return Err(format!("unknown entity option {}", k).into())
+        }
+    }
+
+    let name = name.ok_or("entity name is missing")?;
+    let route = route.unwrap_or(format!("/{}", name)).trim_end_matches('/').to_string();
+    if !route.starts_with('/') {
+        return Err(format!("route of entity {} must start with /", name).into())
+    }
+
+    Ok((EntityType { name, route, id_rule }, parse_map(&merged)?))
+}
+
+fn parse_id_rule(value: &Value) -> Result<IdRule, Box<dyn Error>> {
+    let r = match value {
+        Value::String(rule) => match rule.as_str() {
+            "any" => IdRule::Any,
+            "integer" => IdRule::Integer,
+            "uuid" => IdRule::Uuid,
+            _ => return Err(format!("unknown id rule {}", rule).into())
+        },
+        Value::Object(obj) => match obj.get("regex") {
+            Some(Value::String(re)) => IdRule::Regex(Regex::new(&format!("^(?:{})$", re))?),
+            _ => return Err("id rule object needs a regex".into())
+        },
+        _ => return Err("invalid id rule".into())
+    };
+
+    Ok(r)
+}
+
+fn parse_map(raw: &serde_json::Map<String, Value>) -> Result<Config, Box<dyn Error>> {
+    let mut cfg = Config::new(vec![]);
+
+    for (k, v) in raw.iter() {
+        match k.as_str() {
+            "connections" => cfg.connections = parse_connections(v)?,
+            "request_timeout_ms" => cfg.request_timeout_ms = Some(parse_u64(v)?),
+            "cache" => cfg.cache_capacity = parse_cache(v)?,
+            "max_batch_ids" => match parse_u64(v)? {
+                0 => return Err("max_batch_ids must be positive".into()),
+                n => cfg.max_batch_ids = n as usize,
+            },
+            "export" => cfg.export = Some(parse_export(v)?),
+            "params" => cfg.params = parse_params(v)?,
+            "masking" => cfg.masking = Some(parse_masking(v)?),
+            "admin_tokens" => cfg.admin_tokens = match v {
+                Value::Array(tokens) => tokens.iter().map(|t| parse_digest(&parse_string(t)?)).collect::<Result<_, _>>()?,
+                _ => return Err("admin_tokens is not an array".into())
+            },
+            _ => {
+                let (groups, computed) = parse_set(v)?;
+                cfg.attr_groups.push((k.to_owned(), groups));
+                if !computed.is_empty() {
+                    cfg.computed.push((k.to_owned(), computed));
+                }
+            }
+        }
+    }
+
+    group_levels(&cfg)?;
+    check_conflicts(&cfg)?;
+    check_computed(&mut cfg)?;
+    Ok(cfg)
+}
+
+/// Whether `a` and `b` are the same name or one nests under the other.
+fn overlaps(a: &str, b: &str) -> bool {
+    let under = |a: &str, b: &str| a.strip_prefix(b).is_some_and(|rest| rest.starts_with('.'));
+    a == b || under(a, b) || under(b, a)
+}
+
+/// Orders the computed attributes of every set so each comes after the computed
+/// attributes it reads, checks their expressions against their declared types
+/// and records the fetched attributes they read.
+fn check_computed(cfg: &mut Config) -> Result<(), Box<dyn Error>> {
+    for (set, computed) in cfg.computed.iter_mut() {
+        let groups = cfg.attr_groups.iter().find(|(s, _)| s == set).map(|(_, g)| g.as_slice()).unwrap_or_default();
+        let mut pending = std::mem::take(computed);
+        while !pending.is_empty() {
+            let mut c = match pending.iter().position(|c| c.expr.attrs().iter().all(|a| !pending.iter().any(|p| p.name == *a))) {
+                Some(i) => pending.remove(i),
+                None => {
+                    let names = pending.iter().map(|c| c.name.as_str()).collect::<Vec<_>>().join(", ");
+                    return Err(format!("computed attributes {} of {} read each other", names, set).into())
+                }
+            };
+
+            if let Some(other) = groups.iter().flat_map(|g| g.outputs()).chain(computed.iter().chain(pending.iter()).map(|o| o.name.as_str())).find(|o| overlaps(o, &c.name)) {
+                return Err(format!("computed {} of {} clashes with {}", c.name, set, other).into())
+            }
+
+            let done = &*computed;
+            let ty = c.expr.check(&|a| attr_type(groups, done, a)).map_err(|e| format!("computed {} of {}: {}", c.name, set, e))?;
+            if ty != c.ty {
+                return Err(format!("computed {} of {} is declared {} but its expression is {}", c.name, set, c.ty.name(), ty.name()).into())
+            }
+
+            for a in c.expr.attrs() {
+                match done.iter().find(|d| d.name == a) {
+                    Some(d) => c.sources.extend(d.sources.iter().cloned()),
+                    None => c.sources.push(a.to_string()),
+                }
+            }
+            c.sources.sort();
+            c.sources.dedup();
+            computed.push(c);
+        }
+    }
+
+    Ok(())
+}
+
+/// Type of attribute `attr` read by a computed attribute, one of the fetched
+/// single values of the set or an earlier computed attribute.
+fn attr_type(groups: &[AttributeGroup], computed: &[Computed], attr: &str) -> Result<Type, String> {
+    if let Some(c) = computed.iter().find(|c| c.name == attr) {
+        return Ok(c.ty)
+    }
+
+    let props = groups.iter()
+        .filter(|g| g.outputs().contains(&attr))
+        .map(|g| match g.exp_rows {
+            ExpectedRows::Multiple => None,
+            ExpectedRows::Single => g.select_attrs.iter().find(|(k, p)| p.convert_name.as_deref().unwrap_or(k) == attr).map(|(_, p)| p),
+        })
+        .collect::<Vec<_>>();
+    if props.is_empty() {
+        return Err(format!("unknown attribute {}", attr))
+    }
+
+    match props.iter().map(|p| p.filter(|p| !p.modifiers.iter().any(|m| matches!(m, Modifier::Split(_))) && p.merge != Some(Merge::CollectIntoArray))).collect::<Option<Vec<_>>>() {
+        Some(props) => Ok(props[0].ptype),
+        None => Err(format!("{} is not a single value", attr)),
+    }
+}
+
+/// The `!Merge` strategy of output `attr` of `set`, from any group producing it.
+pub fn merge_rule<'a>(cfg: &'a Config, set: &str, attr: &str) -> Option<&'a Merge> {
+    let (_, groups) = cfg.attr_groups.iter().find(|(name, _)| name == set)?;
+    groups.iter()
+        .flat_map(|g| g.select_attrs.iter())
+        .find_map(|(k, p)| p.merge.as_ref().filter(|_| p.convert_name.as_deref().unwrap_or(k) == attr))
+}
+
+/// Outputs produced by several groups of a set need one `!Merge` strategy,
+/// a single group cannot produce an output twice and a dotted output cannot
+/// nest under another output.
+fn check_conflicts(cfg: &Config) -> Result<(), Box<dyn Error>> {
+    for (set, groups) in cfg.attr_groups.iter() {
+        let mut seen: Vec<(&str, Vec<usize>)> = vec![];
+        for (j, group) in groups.iter().enumerate() {
+            for name in group.outputs() {
+                match seen.iter_mut().find(|(n, _)| *n == name) {
+                    Some((_, js)) if js.contains(&j) => {
+                        return Err(format!("group {} of {} produces {} twice", j, set, name).into())
+                    }
+                    Some((_, js)) => js.push(j),
+                    None => seen.push((name, vec![j])),
+                }
+            }
+        }
+
+        for (name, _) in seen.iter() {
+            if let Some((other, _)) = seen.iter().find(|(o, _)| o.strip_prefix(name).is_some_and(|rest| rest.starts_with('.'))) {
+                return Err(format!("{} of {} is both a value and the object of {}", name, set, other).into())
+            }
+        }
+
+        for (name, js) in seen.iter().filter(|(_, js)| js.len() > 1) {
+            let rules = groups.iter()
+                .flat_map(|g| g.select_attrs.iter())
+                .filter(|(k, p)| p.convert_name.as_deref().unwrap_or(k) == *name)
+                .filter_map(|(_, p)| p.merge.as_ref())
+                .collect::<Vec<_>>();
+            if rules.is_empty() {
+                let js = js.iter().map(|j| j.to_string()).collect::<Vec<_>>().join(", ");
+                return Err(format!("{} of {} is produced by groups {} without a !Merge strategy", name, set, js).into())
+            }
+            if rules.iter().any(|r| *r != rules[0]) {
+                return Err(format!("conflicting !Merge strategies for {} of {}", name, set).into())
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The set and position of a group producing output `attr` of `set`.
+/// Inputs only read outputs of a single group, see `group_level`.
+pub fn producer(cfg: &Config, set: &str, attr: &str) -> Option<(usize, usize)> {
+    let s = cfg.attr_groups.iter().position(|(name, _)| name == set)?;
+    let j = cfg.attr_groups[s].1.iter().position(|g| g.outputs().contains(&attr))?;
+    Some((s, j))
+}
+
+/// Orders the groups by their inputs: a group without inputs is on level 0,
+/// any other one level above the highest of the groups it reads. Rejects
+/// inputs that cannot be resolved and dependency cycles.
+pub fn group_levels(cfg: &Config) -> Result<Vec<Vec<usize>>, Box<dyn Error>> {
+    let mut levels = cfg.attr_groups.iter().map(|(_, groups)| vec![None; groups.len()]).collect::<Vec<_>>();
+    for (s, (_, groups)) in cfg.attr_groups.iter().enumerate() {
+        for j in 0..groups.len() {
+            group_level(cfg, (s, j), &mut levels, &mut vec![])?;
+        }
+    }
+
+    Ok(levels.into_iter().map(|l| l.into_iter().map(Option::unwrap_or_default).collect()).collect())
+}
+
+fn group_level(cfg: &Config, (s, j): (usize, usize), levels: &mut Vec<Vec<Option<usize>>>, path: &mut Vec<(usize, usize)>) -> Result<usize, Box<dyn Error>> {
+    if let Some(level) = levels[s][j] {
+        return Ok(level)
+    }
+
+    let set = &cfg.attr_groups[s].0;
+    if path.contains(&(s, j)) {
+        return Err(format!("dependency cycle through group {} of {}", j, set).into())
+    }
+    path.push((s, j));
+
+    let mut level = 0;
+    for input in cfg.attr_groups[s].1[j].inputs.iter() {
+        if input.name == "id" || cfg.params.iter().any(|p| p.name == input.name) {
+            return Err(format!("input {} of group {} of {} clashes with a param", input.name, j, set).into())
+        }
+        let (ps, pj) = producer(cfg, &input.set, &input.attr)
+            .ok_or_else(|| format!("input {} of group {} of {} reads unknown output {}.{}", input.name, j, set, input.set, input.attr))?;
+        if cfg.attr_groups[ps].1[pj].exp_rows == ExpectedRows::Multiple {
+            return Err(format!("input {} of group {} of {} reads {}.{} of a group returning multiple rows", input.name, j, set, input.set, input.attr).into())
+        }
+        if cfg.attr_groups[ps].1.iter().filter(|g| g.outputs().contains(&input.attr.as_str())).count() > 1 {
+            return Err(format!("input {} of group {} of {} reads {}.{} that is merged from several groups", input.name, j, set, input.set, input.attr).into())
+        }
+        level = level.max(group_level(cfg, (ps, pj), levels, path)? + 1);
+    }
+
+    path.pop();
+    levels[s][j] = Some(level);
+    Ok(level)
+}
+
+/// Parses the groups of a set and its `{"computed": {...}}` entries.
+fn parse_set(value: &Value) -> Result<(Vec<AttributeGroup>, Vec<Computed>), Box<dyn Error>> {
+    let entries = match value {
+        Value::Array(entries) => entries,
+        _ => return Err("not an array".into())
+    };
+
+    let (mut groups, mut computed) = (vec![], vec![]);
+    for v in entries {
+        match v.get("computed") {
+            Some(_) if v.as_object().is_some_and(|obj| obj.len() > 1) => return Err("computed cannot be part of a group".into()),
+            Some(c) => computed.extend(parse_computed(c)?),
+            None => groups.push(parse_group(v)?),
+        }
+    }
+
+    Ok((groups, computed))
+}
+
+/// Parses `"name": ["Type::Boolean", "!Expr::age >= 18"]` entries, see `expr::parse`.
+fn parse_computed(value: &Value) -> Result<Vec<Computed>, Box<dyn Error>> {
+    let obj = match value {
+        Value::Object(obj) => obj,
+        _ => return Err("computed is not an object".into())
+    };
+
+    let mut computed = vec![];
+    for (name, v) in obj {
+        if name.split('.').any(str::is_empty) {
+            return Err(format!("invalid name {}", name).into())
+        }
+        let props = match v {
+            Value::Array(props) => props,
+            _ => return Err(format!("computed {} is not an array", name).into())
+        };
+
+        let (mut ty, mut expr) = (None, None);
+        for p in props {
+            let p = parse_string(p)?;
+            match p.split_once("::") {
+                Some(("Type", t)) => ty = Some(parse_type(t)?),
+                Some(("!Expr", e)) => expr = Some(expr::parse(e).map_err(|e| format!("expression of {}: {}", name, e))?),
+                _ => return Err(format!("unknown property {} of computed {}", p, name).into())
+            }
+        }
+
+        computed.push(Computed {
+            name: name.to_string(),
+            ty: ty.ok_or_else(|| format!("computed {} needs a type", name))?,
+            expr: expr.ok_or_else(|| format!("computed {} needs an !Expr", name))?,
+            sources: vec![],
+        });
+    }
+
+    Ok(computed)
+}
+
+fn parse_type(value: &str) -> Result<Type, Box<dyn Error>> {
+    let t = match value {
+        "String" => TypeString,
+        "Number" => Number,
+        "JSON" => JSON,
+        "Boolean" => Boolean,
+        _=> return Err("invalid type".into())
+    };
+
+    Ok(t)
+}
+
+fn parse_attr_set(value: &Value) -> Result<Vec<AttributeGroup>, Box<dyn Error>> {
+    match value {
+        Value::Array(groups) => groups.iter().map(parse_group).collect(),
+        _ => Err("not an array".into())
+    }
+}
+
+fn parse_group(value: &Value) -> Result<AttributeGroup, Box<dyn Error>> {
+    let group_obj = match value {
+        Value::Object(group_obj) => group_obj,
+        _ => return Err("group is not an object".into())
+    };
+
+    let mut attr_group = AttributeGroup::new();
+    for (k, v) in group_obj {
+        match k.to_string().as_str() {
+            "connection"=> {
+                attr_group.conn = parse_connection(v)?
+            }
+            "query" => {
+                attr_group.query = parse_query(v)?
+            }
+            "expected_rows" => {
+                attr_group.exp_rows = parse_exp_rows(v)?
+            }
+            "select_attributes" => {
+                attr_group.select_attrs = parse_select_attributes(v)?
+            }
+            "timeout_ms" => {
+                attr_group.timeout_ms = Some(parse_u64(v)?)
+            }
+            "retry" => {
+                attr_group.retry = Some(parse_retry(v)?)
+            }
+            "optional" => {
+                attr_group.optional = parse_bool(v)?
+            }
+            "cache_ttl" => {
+                attr_group.cache_ttl = parse_u64(v)?
+            }
+            "key_column" => {
+                attr_group.key_column = Some(parse_string(v)?)
+            }
+            "inputs" => {
+                attr_group.inputs = parse_inputs(v)?
+            }
+            "name" => {
+                attr_group.name = Some(parse_string(v)?)
+            }
+            "children" => {
+                attr_group.children = parse_attr_set(v)?
+            }
+            "parent_column" => {
+                attr_group.parent_column = Some(parse_string(v)?)
+            }
+            "join" => {
+                attr_group.join = Some(Box::new(parse_join(v)?))
+            }
+            _ => {}
+        }
+    }
+
+    check_children(&attr_group)?;
+    check_join(&attr_group)?;
+    Ok(attr_group)
+}
+
+/// Children nest one level below a named group returning multiple rows.
+fn check_children(group: &AttributeGroup) -> Result<(), Box<dyn Error>> {
+    if group.children.is_empty() {
+        return Ok(())
+    }
+    if group.exp_rows != ExpectedRows::Multiple || group.name.is_none() {
+        return Err("a group with children needs a name and multiple expected rows".into())
+    }
+    if group.children.iter().any(|c| !c.children.is_empty() || !c.inputs.is_empty()) {
+        return Err("children cannot have children or inputs".into())
+    }
+
+    Ok(())
+}
+
+fn parse_join(value: &Value) -> Result<Join, Box<dyn Error>> {
+    let obj = match value {
+        Value::Object(obj) => obj,
+        _ => return Err("join is not an object".into())
+    };
+
+    let source = |side: &str| match obj.get(side) {
+        Some(v) => parse_group(v),
+        None => Err(format!("join needs a {} group", side).into()),
+    };
+    let (left_key, right_key) = match obj.get("on") {
+        Some(Value::String(col)) => (col.to_string(), col.to_string()),
+        Some(Value::Object(on)) => match (on.get("left"), on.get("right")) {
+            (Some(l), Some(r)) => (parse_string(l)?, parse_string(r)?),
+            _ => return Err("join on needs a left and a right column".into())
+        },
+        _ => return Err("join needs an on column".into())
+    };
+    let kind = match obj.get("kind").map(parse_string).transpose()?.as_deref() {
+        None | Some("inner") => JoinKind::Inner,
+        Some("left") => JoinKind::Left,
+        Some(kind) => return Err(format!("unknown join kind {}", kind).into())
+    };
+
+    Ok(Join { left: source("left")?, right: source("right")?, left_key, right_key, kind })
+}
+
+/// A join group runs its sources instead of a query, so it cannot batch or
+/// have children, and its sources are plain queries.
+fn check_join(group: &AttributeGroup) -> Result<(), Box<dyn Error>> {
+    let join = match &group.join {
+        Some(join) => join,
+        None => return Ok(())
+    };
+    if !group.query.is_empty() || group.key_column.is_some() || !group.children.is_empty() {
+        return Err("a join group cannot have a query, key_column or children".into())
+    }
+    for source in [&join.left, &join.right] {
+        if source.query.is_empty() || source.join.is_some() || !source.children.is_empty() {
+            return Err("join sources need a query and cannot be joins or have children".into())
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_masking(value: &Value) -> Result<Masking, Box<dyn Error>> {
+    let obj = match value {
+        Value::Object(obj) => obj,
+        _ => return Err("masking is not an object".into())
+    };
+
+    let mut masking = Masking { header: String::default(), unmasked: vec![], tokens: vec![], trust_role_header: false };
+    for (k, v) in obj {
+        match k.as_str() {
+            "header" => masking.header = parse_string(v)?,
+            "unmasked" => masking.unmasked = match v {
+                Value::Array(roles) => roles.iter().map(parse_string).collect::<Result<_, _>>()?,
+                _ => return Err("masking unmasked is not an array".into())
+            },
+            "tokens" => masking.tokens = match v {
+                Value::Object(tokens) => tokens.iter()
+                    .map(|(digest, role)| Ok((parse_digest(digest)?, parse_string(role)?)))
+                    .collect::<Result<_, Box<dyn Error>>>()?,
+                _ => return Err("masking tokens is not an object".into())
+            },
+            "trust_role_header" => masking.trust_role_header = parse_bool(v)?,
+            _ => return Err(format!("unknown masking option {}", k).into())
+        }
+    }
+    if masking.header.is_empty() {
+        return Err("masking needs a header".into())
+    }
+    if masking.tokens.is_empty() != masking.trust_role_header {
+        return Err("masking needs either tokens or trust_role_header".into())
+    }
+
+    Ok(masking)
 }
 
-pub struct Properties {
-    pub ptype: Type,
-    pub convert_name: Option<String>,
-    pub return_attribute: Option<String>
+/// Checks a token digest is a hex SHA-256 and lowercases it.
+fn parse_digest(digest: &str) -> Result<String, Box<dyn Error>> {
+    if digest.len() != 64 || !digest.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("token {} is not a hex sha256 digest", digest).into())
+    }
+
+    Ok(digest.to_ascii_lowercase())
 }
 
-impl Properties {
-    pub fn new() -> Self {
-        Self { ptype: TypeString, convert_name: None, return_attribute: None }
+fn check_date_format(fmt: &str) -> Result<(), Box<dyn Error>> {
+    let mut chars = fmt.chars();
+    while let Some(c) = chars.next() {
+        if c == '%' && !chars.next().is_some_and(|s| DATE_SPECIFIERS.contains(s)) {
+            return Err(format!("unsupported date format {}", fmt).into())
+        }
     }
+
+    Ok(())
 }
 
-pub struct Config {
-    pub attr_groups: Vec<(String,Vec<AttributeGroup>)>
+fn parse_merge(value: &str) -> Result<Merge, Box<dyn Error>> {
+    let r = match value.split_once(':') {
+        Some(("prefer", conn)) => Merge::Prefer(parse_connection(&Value::String(conn.to_string()))?),
+        _ => match value {
+            "first_non_null" => Merge::FirstNonNull,
+            "collect_into_array" => Merge::CollectIntoArray,
+            "error" => Merge::Error,
+            _ => return Err(format!("unknown merge strategy {}", value).into())
+        },
+    };
+
+    Ok(r)
 }
 
-impl Config {
-    pub fn new(attr_groups: Vec<(String,Vec<AttributeGroup>)>) -> Self {
-        Self { attr_groups }
+fn parse_inputs(value: &Value) -> Result<Vec<GroupInput>, Box<dyn Error>> {
+    let obj = match value {
+        Value::Object(obj) => obj,
+        _ => return Err("inputs is not an object".into())
+    };
+
+    obj.iter().map(|(name, v)| {
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(format!("invalid input name {}", name).into())
+        }
+        let output = parse_string(v)?;
+        let (set, attr) = output.split_once('.').ok_or_else(|| format!("input {} is not set.attribute", name))?;
+        Ok(GroupInput { name: name.to_string(), set: set.to_string(), attr: attr.to_string() })
+    }).collect()
+}
+
+fn parse_connections(value: &Value) -> Result<Vec<(Connection, ConnectionSettings)>, Box<dyn Error>> {
+    let r = match value {
+        Value::Object(obj) => {
+            let mut conns = vec![];
+            for (k, v) in obj {
+                let conn = parse_connection(&Value::String(k.to_string()))?;
+                let mut settings = ConnectionSettings::new();
+                match v {
+                    Value::Object(settings_obj) => {
+                        for (k, v) in settings_obj {
+                            match k.as_str() {
+                                "url" => settings.url = Some(parse_string(v)?),
+                                "timeout_ms" => settings.timeout_ms = Some(parse_u64(v)?),
+                                "retry" => settings.retry = Some(parse_retry(v)?),
+                                "circuit_breaker" => settings.circuit_breaker = Some(parse_breaker(v)?),
+                                _ => {}
+                            }
+                        }
+                    }
+                    _ => return Err("connection settings is not an object".into())
+                }
+                conns.push((conn, settings));
+            }
+
+            conns
+        }
+        _ => return Err("connections is not an object".into())
+    };
+
+    Ok(r)
+}
+
+fn parse_cache(value: &Value) -> Result<usize, Box<dyn Error>> {
+    let mut capacity = 10_000;
+    match value {
+        Value::Object(obj) => {
+            for (k, v) in obj {
+                match k.as_str() {
+                    "capacity" => capacity = parse_u64(v)? as usize,
+                    _ => return Err(format!("unknown cache option {}", k).into())
+                }
+            }
+        }
+        _ => return Err("cache is not an object".into())
     }
+
+    Ok(capacity)
 }
 
-pub struct AttributeGroup {
-    pub conn: Connection,
-    pub query: String,
-    pub exp_rows: ExpectedRows,
-    pub select_attrs: Vec<(String, Properties)>
+fn parse_params(value: &Value) -> Result<Vec<ParamSpec>, Box<dyn Error>> {
+    let obj = match value {
+        Value::Object(obj) => obj,
+        _ => return Err("params is not an object".into())
+    };
+
+    let mut params = vec![];
+    for (name, v) in obj {
+        if name == "id" {
+            return Err("id is a built-in param".into())
+        }
+
+        let spec = match v {
+            Value::Object(spec) => spec,
+            _ => return Err(format!("param {} is not an object", name).into())
+        };
+
+        let mut from = None;
+        let mut key = name.to_string();
+        let mut ty = ParamType::String;
+        let mut default = None;
+        for (k, v) in spec {
+            match k.as_str() {
+                "from" => from = Some(parse_string(v)?),
+                "name" => key = parse_string(v)?,
+                "type" => ty = parse_param_type(v)?,
+                "default" => default = Some(parse_string(v)?),
+                _ => return Err(format!("unknown option {} of param {}", k, name).into())
+            }
+        }
+
+        let source = match from.as_deref() {
+            Some("path") => ParamSource::Path(key),
+            Some("query") => ParamSource::Query(key),
+            Some("header") => ParamSource::Header(key),
+            _ => return Err(format!("param {} needs \"from\": path, query or header", name).into())
+        };
+
+        if let Some(d) = &default {
+            check_param(ty, d).map_err(|e| format!("default of param {}: {}", name, e))?;
+        }
+
+        params.push(ParamSpec { name: name.to_string(), source, ty, default });
+    }
+
+    Ok(params)
 }
 
-impl AttributeGroup {
-    pub fn new() -> Self {
-        Self {
-            conn: Connection::PostgresSQL,
-            query: String::default(),
-            exp_rows: ExpectedRows::Single,
-            select_attrs: vec![],
+fn parse_param_type(value: &Value) -> Result<ParamType, Box<dyn Error>> {
+    let r = match value {
+        Value::String(ty) => match ty.as_str() {
+            "string" => ParamType::String,
+            "integer" => ParamType::Integer,
+            "number" => ParamType::Number,
+            "boolean" => ParamType::Boolean,
+            "date" => ParamType::Date,
+            _ => return Err(format!("unknown param type {}", ty).into())
+        },
+        _ => return Err("param type is not a string".into())
+    };
+
+    Ok(r)
+}
+
+/// Checks that `value` is a valid `ty`.
+pub fn check_param(ty: ParamType, value: &str) -> Result<(), String> {
+    let ok = match ty {
+        ParamType::String => true,
+        ParamType::Integer => value.parse::<i64>().is_ok(),
+        ParamType::Number => value.parse::<f64>().is_ok_and(f64::is_finite),
+        ParamType::Boolean => value == "true" || value == "false",
+        ParamType::Date => {
+            let parts = value.split('-').collect::<Vec<_>>();
+            parts.len() == 3 && parts[0].len() == 4 && parts[1].len() == 2 && parts[2].len() == 2
+                && parts.iter().all(|p| p.bytes().all(|b| b.is_ascii_digit()))
+                && (1..=12).contains(&parts[1].parse::<u32>().unwrap_or(0))
+                && (1..=31).contains(&parts[2].parse::<u32>().unwrap_or(0))
         }
+    };
+
+    if !ok {
+        return Err(format!("{} is not a valid {}", value, ty.name()))
     }
+
+    Ok(())
 }
 
-pub fn parse(data: &[u8]) -> Result<Config, Box<dyn Error>> {
-    let raw: HashMap<String, Value> = serde_json::from_slice(data)?;
-    let mut groups = vec![];
+fn parse_export(value: &Value) -> Result<ExportSource, Box<dyn Error>> {
+    let mut source = ExportSource::new();
+    match value {
+        Value::Object(obj) => {
+            for (k, v) in obj {
+                match k.as_str() {
+                    "connection" => source.conn = parse_connection(v)?,
+                    "query" => source.query = parse_query(v)?,
+                    "column" => source.column = parse_string(v)?,
+                    "page_size" => source.page_size = parse_u64(v)? as usize,
+                    "batch_size" => source.batch_size = parse_u64(v)? as usize,
+                    "concurrency" => source.concurrency = parse_u64(v)? as usize,
+                    "timeout_ms" => source.timeout_ms = Some(parse_u64(v)?),
+                    _ => return Err(format!("unknown export option {}", k).into())
+                }
+            }
+        }
+        _ => return Err("export is not an object".into())
+    }
 
-    for (k, v) in raw.iter() {
-        let group_name = k;
-        let mut props = vec![];
-        match v {
-            Value::Array(group) => {
-                for v in group {
-                    match v {
-                        Value::Object(group_obj) => {
-                            let mut attr_group = AttributeGroup::new();
-                            for (k, v) in group_obj {
-                                match k.to_string().as_str() {
-                                    "connection"=> {
-                                        attr_group.conn = parse_connection(v)?
-                                    }
-                                    "query" => {
-                                        attr_group.query = parse_query(v)?
-                                    }
-                                    "expected_rows" => {
-                                        attr_group.exp_rows = parse_exp_rows(v)?
-                                    }
-                                    "select_attributes" => {
-                                        attr_group.select_attrs = parse_select_attributes(v)?
-                                    }
-                                    _ => {}
-                                }
-                            }
+    if source.query.is_empty() {
+        return Err("export query is missing".into())
+    }
+    if !source.query.contains("__LIMIT__") || !source.query.contains("__OFFSET__") {
+        return Err("export query must page with __LIMIT__ and __OFFSET__".into())
+    }
+    if source.page_size == 0 || source.batch_size == 0 || source.concurrency == 0 {
+        return Err("export page_size, batch_size and concurrency must be positive".into())
+    }
 
-                            props.push(attr_group);
+    Ok(source)
+}
+
+fn parse_retry(value: &Value) -> Result<RetryPolicy, Box<dyn Error>> {
+    let mut policy = RetryPolicy::new();
+    match value {
+        Value::Object(obj) => {
+            for (k, v) in obj {
+                match k.as_str() {
+                    "max_attempts" => policy.max_attempts = parse_u64(v)? as u32,
+                    "backoff_ms" => policy.backoff_ms = parse_u64(v)?,
+                    "max_backoff_ms" => policy.max_backoff_ms = parse_u64(v)?,
+                    "retry_on" => {
+                        policy.retry_on = match v {
+                            Value::Array(kinds) => kinds.iter().map(parse_error_kind).collect::<Result<_, _>>()?,
+                            _ => return Err("retry_on is not an array".into())
                         }
-                        _=> return Err("group is not an object".into())
                     }
+                    _ => return Err(format!("unknown retry option {}", k).into())
+                }
+            }
+        }
+        _ => return Err("retry is not an object".into())
+    }
+
+    if policy.max_attempts == 0 {
+        return Err("max_attempts must be at least 1".into())
+    }
+
+    Ok(policy)
+}
+
+fn parse_breaker(value: &Value) -> Result<BreakerSettings, Box<dyn Error>> {
+    let mut settings = BreakerSettings::new();
+    match value {
+        Value::Object(obj) => {
+            for (k, v) in obj {
+                match k.as_str() {
+                    "failure_threshold" => settings.failure_threshold = parse_u64(v)? as u32,
+                    "open_ms" => settings.open_ms = parse_u64(v)?,
+                    "half_open_probes" => settings.half_open_probes = parse_u64(v)? as u32,
+                    _ => return Err(format!("unknown circuit_breaker option {}", k).into())
                 }
             }
-            _ => return Err("not an array".into())
         }
-        groups.push((group_name.to_owned(), props))
+        _ => return Err("circuit_breaker is not an object".into())
+    }
+
+    if settings.failure_threshold == 0 || settings.half_open_probes == 0 {
+        return Err("failure_threshold and half_open_probes must be at least 1".into())
+    }
+
+    Ok(settings)
+}
+
+fn parse_error_kind(value: &Value) -> Result<ErrorKind, Box<dyn Error>> {
+    let r = match parse_string(value)?.as_str() {
+        "deadlock" => ErrorKind::Deadlock,
+        "connection" => ErrorKind::Connection,
+        "pool_exhausted" => ErrorKind::PoolExhausted,
+        "other" => ErrorKind::Other,
+        v => return Err(format!("unknown error kind {}", v).into())
+    };
+
+    Ok(r)
+}
+
+fn parse_string(value: &Value) -> Result<String, Box<dyn Error>> {
+    match value {
+        Value::String(v) => Ok(v.to_string()),
+        _ => Err("value is not a string".into())
+    }
+}
+
+fn parse_bool(value: &Value) -> Result<bool, Box<dyn Error>> {
+    match value {
+        Value::Bool(v) => Ok(*v),
+        _ => Err("value is not a boolean".into())
     }
+}
 
-    Ok(Config::new(groups))
+fn parse_u64(value: &Value) -> Result<u64, Box<dyn Error>> {
+    match value.as_u64() {
+        Some(v) => Ok(v),
+        None => Err("value is not a positive integer".into())
+    }
 }
 
 fn parse_connection(value: &Value) -> Result<Connection, Box<dyn Error>> {
@@ -129,6 +1310,9 @@ fn parse_query(value: &Value) -> Result<String, Box<dyn Error>> {
         Value::String(query) => query.to_string(),
         _ => return Err("invalid query".into())
     };
+    if connection::bind_id(&r).is_none() {
+        return Err("__PID__ must be quoted on its own, as '__PID__'".into())
+    }
 
     Ok(r)
 }
@@ -160,19 +1344,46 @@ fn parse_select_attributes(value: &Value) -> Result<Vec<(String, Properties)>, B
                                 Value::String(prop) => {
                                     let (attr_type, attr_value) = prop.split_once("::").unwrap_or((prop, ""));
                                     match attr_type {
-                                        "Type" => {
-                                            let t = match attr_value {
-                                                "String" => TypeString,
-                                                "Number" => Number,
-                                                "JSON" => JSON,
-                                                "Boolean" => Boolean,
-                                                _=> return Err("invalid type".into())
-                                            };
-
-                                            props.ptype = t;
-                                        },
-                                        "!ConvertName" => props.convert_name = Some(attr_value.to_string()),
+                                        "Type" => props.ptype = parse_type(attr_value)?,
+                                        "!ConvertName" => {
+                                            if attr_value.split('.').any(str::is_empty) {
+                                                return Err(format!("invalid name {}", attr_value).into())
+                                            }
+                                            props.convert_name = Some(attr_value.to_string())
+                                        }
                                         "ReturnAttribute" => props.return_attribute = Some(attr_value.to_string()),
+                                        "!Merge" => props.merge = Some(parse_merge(attr_value)?),
+                                        "!Trim" => props.modifiers.push(Modifier::Trim),
+                                        "!Lower" => props.modifiers.push(Modifier::Lower),
+                                        "!Upper" => props.modifiers.push(Modifier::Upper),
+                                        "!Regex" => {
+                                            let (pattern, replacement) = attr_value.rsplit_once("::")
+                                                .ok_or_else(|| format!("!Regex of {} needs a pattern and a replacement", name))?;
+                                            let re = Regex::new(pattern).map_err(|e| format!("!Regex of {}: {}", name, e))?;
+                                            props.modifiers.push(Modifier::Regex(re, replacement.to_string()))
+                                        }
+                                        "!DateFormat" => {
+                                            check_date_format(attr_value)?;
+                                            props.modifiers.push(Modifier::DateFormat(attr_value.to_string()))
+                                        }
+                                        "!Default" => props.modifiers.push(Modifier::Default(attr_value.to_string())),
+                                        "!Mask" => props.modifiers.push(match attr_value {
+                                            "email" => Modifier::MaskEmail,
+                                            "last4" => Modifier::MaskLast4,
+                                            _ => return Err(format!("unknown mask {} of {}", attr_value, name).into())
+                                        }),
+                                        "!Hash" => props.modifiers.push(match attr_value {
+                                            "sha256" => Modifier::Sha256,
+                                            _ => return Err(format!("unknown hash {} of {}", attr_value, name).into())
+                                        }),
+                                        "!Redact" => props.modifiers.push(Modifier::Redact),
+                                        "!UnmaskFor" => props.unmask_for.extend(attr_value.split(',').map(str::trim).filter(|r| !r.is_empty()).map(String::from)),
+                                        "!Split" => {
+                                            if attr_value.is_empty() {
+                                                return Err(format!("!Split of {} needs a separator", name).into())
+                                            }
+                                            props.modifiers.push(Modifier::Split(attr_value.to_string()))
+                                        }
                                         _=> return Err("invalid attr type".into())
                                     }
                                 }
@@ -195,7 +1406,7 @@ fn parse_select_attributes(value: &Value) -> Result<Vec<(String, Properties)>, B
 
 #[cfg(test)]
 mod test {
-    use crate::config::config::{Connection, ExpectedRows};
+    use crate::config::config::{Connection, ErrorKind, ExpectedRows, ParamSource, ParamType, Type};
 
     #[test]
     fn parse() {
@@ -206,8 +1417,8 @@ mod test {
         "query": "select * from users where id = '__PID__'",
         "expected_rows": "single",
         "select_attributes": {
-            "fn": ["Type::String", "!ConvertName::firstname", "!Audit"],
-            "ln": ["Type::String", "!ConvertName::lastname", "!Audit"],
+            "fn": ["Type::String", "!ConvertName::firstname"],
+            "ln": ["Type::String", "!ConvertName::lastname"],
             "currency": ["Type::String"],
             "age": ["Type::Number"]
         }
@@ -224,11 +1435,11 @@ mod test {
         let res = super::parse(data).unwrap();
         assert_eq!(res.attr_groups.len(), 1);
 
-        let (attr, groups) = res.attr_groups.get(0).unwrap();
+        let (attr, groups) = res.attr_groups.first().unwrap();
         assert_eq!(attr, "attributes");
         assert_eq!(groups.len(), 2);
 
-        let gr1 = groups.get(0).unwrap();
+        let gr1 = groups.first().unwrap();
         assert_eq!(gr1.conn, Connection::PostgresSQL, "invalid connection");
         assert_eq!(gr1.query, "select * from users where id = '__PID__'");
         assert_eq!(gr1.exp_rows, ExpectedRows::Single);
@@ -240,4 +1451,388 @@ mod test {
         assert_eq!(gr2.exp_rows, ExpectedRows::Multiple);
         assert_eq!(gr2.select_attrs.len(), 1);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn parse_unknown_modifier() {
+        let unknown = r#"{"attributes": [{"query": "q", "select_attributes": {"fn": ["Type::String", "!Audit"]}}]}"#;
+        assert!(super::parse(unknown.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn parse_timeouts() {
+        let data = r#"{
+"request_timeout_ms": 500,
+"connections": {"mysql": {"url": "mysql://localhost/test", "timeout_ms": 100}},
+"attributes": [
+    {
+        "connection": "mysql",
+        "query": "select * from org where user_id = '__PID__'",
+        "expected_rows": "single",
+        "timeout_ms": 50,
+        "optional": true,
+        "select_attributes": {}
+    }
+]}"#.as_bytes();
+        let res = super::parse(data).unwrap();
+        assert_eq!(res.attr_groups.len(), 1);
+        assert_eq!(res.request_timeout_ms, Some(500));
+
+        let mysql = res.connection(&Connection::MySQL).unwrap();
+        assert_eq!(mysql.url.as_deref(), Some("mysql://localhost/test"));
+        assert_eq!(mysql.timeout_ms, Some(100));
+        assert!(mysql.retry.is_none());
+        assert!(mysql.circuit_breaker.is_none());
+        assert!(res.connection(&Connection::PostgresSQL).is_none());
+
+        assert_eq!(res.attr_groups[0].1[0].timeout_ms, Some(50));
+        assert!(res.attr_groups[0].1[0].optional);
+    }
+
+    #[test]
+    fn parse_retry() {
+        let data = r#"{
+"connections": {"mysql": {"retry": {"max_attempts": 3, "backoff_ms": 10, "retry_on": ["deadlock", "other"]}}},
+"attributes": []
+}"#.as_bytes();
+        let res = super::parse(data).unwrap();
+        let retry = res.connection(&Connection::MySQL).unwrap().retry.as_ref().unwrap();
+        assert_eq!(retry.max_attempts, 3);
+        assert_eq!(retry.backoff_ms, 10);
+        assert_eq!(retry.max_backoff_ms, 1000);
+        assert_eq!(retry.retry_on, vec![ErrorKind::Deadlock, ErrorKind::Other]);
+
+        let unknown = r#"{"connections": {"mysql": {"retry": {"retry_on": ["timeout"]}}}, "attributes": []}"#;
+        assert!(super::parse(unknown.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn parse_breaker() {
+        let data = r#"{
+"connections": {"mysql": {"circuit_breaker": {"failure_threshold": 3, "open_ms": 1000}}},
+"attributes": []
+}"#.as_bytes();
+        let res = super::parse(data).unwrap();
+        let breaker = res.connection(&Connection::MySQL).unwrap().circuit_breaker.as_ref().unwrap();
+        assert_eq!(breaker.failure_threshold, 3);
+        assert_eq!(breaker.open_ms, 1000);
+        assert_eq!(breaker.half_open_probes, 1);
+    }
+
+    #[test]
+    fn parse_cache() {
+        let data = r#"{
+"cache": {"capacity": 100},
+"attributes": [
+    {
+        "connection": "mysql",
+        "query": "select * from org where user_id = '__PID__'",
+        "expected_rows": "single",
+        "cache_ttl": 30,
+        "select_attributes": {}
+    }
+]}"#.as_bytes();
+        let res = super::parse(data).unwrap();
+        assert_eq!(res.cache_capacity, 100);
+        assert_eq!(res.attr_groups[0].1[0].cache_ttl, 30);
+
+        let res = super::parse(r#"{"attributes": []}"#.as_bytes()).unwrap();
+        assert_eq!(res.cache_capacity, 10_000);
+    }
+
+    #[test]
+    fn parse_key_column() {
+        let data = r#"{
+"max_batch_ids": 50,
+"attributes": [
+    {
+        "connection": "mysql",
+        "query": "select * from org where user_id = '__PID__'",
+        "expected_rows": "single",
+        "key_column": "user_id",
+        "select_attributes": {}
+    }
+]}"#.as_bytes();
+        let res = super::parse(data).unwrap();
+        assert_eq!(res.attr_groups[0].1[0].key_column.as_deref(), Some("user_id"));
+        assert_eq!(res.max_batch_ids, 50);
+        assert!(super::parse(r#"{"max_batch_ids": 0, "attributes": []}"#.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn parse_pid_query() {
+        let like = r#"{"attributes": [{"query": "select * from org where name like '%__PID__%'", "select_attributes": {}}]}"#;
+        assert!(super::parse(like.as_bytes()).is_err());
+        let bare = r#"{"attributes": [{"query": "select * from org where user_id = __PID__", "select_attributes": {}}]}"#;
+        assert!(super::parse(bare.as_bytes()).is_ok());
+    }
+
+    #[test]
+    fn parse_export() {
+        let data = r#"{
+"export": {"connection": "mysql", "query": "select id from users limit __LIMIT__ offset __OFFSET__", "page_size": 500, "timeout_ms": 2000},
+"attributes": []
+}"#.as_bytes();
+        let res = super::parse(data).unwrap();
+        let export = res.export.as_ref().unwrap();
+        assert_eq!(export.conn, Connection::MySQL);
+        assert_eq!(export.column, "id");
+        assert_eq!(export.page_size, 500);
+        assert_eq!(export.batch_size, 100);
+        assert_eq!(export.concurrency, 4);
+        assert_eq!(export.timeout_ms, Some(2000));
+
+        let unpaged = r#"{"export": {"query": "select id from users"}, "attributes": []}"#;
+        assert!(super::parse(unpaged.as_bytes()).is_err());
+        let empty = r#"{"export": {"query": "select id from users limit __LIMIT__ offset __OFFSET__", "page_size": 0}, "attributes": []}"#;
+        assert!(super::parse(empty.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn parse_entities() {
+        let data = r#"{
+"request_timeout_ms": 500,
+"entities": [
+    {
+        "name": "user",
+        "route": "/id/",
+        "id": "integer",
+        "sets": {
+            "attributes": [{"connection": "postgres", "query": "select * from users where id = '__PID__'", "select_attributes": {}}]
+        }
+    },
+    {
+        "name": "org",
+        "id": "uuid",
+        "request_timeout_ms": 100,
+        "sets": {
+            "info": [{"connection": "mysql", "query": "select * from orgs where id = '__PID__'", "select_attributes": {}}],
+            "members": [{"connection": "mysql", "query": "select * from members where org_id = '__PID__'", "select_attributes": {}}]
+        }
+    },
+    {
+        "name": "account",
+        "id": {"regex": "acc-[0-9]+"},
+        "sets": {}
+    }
+]}"#.as_bytes();
+        let res = super::parse_entities(data).unwrap();
+        assert_eq!(res.len(), 3);
+
+        let (user, cfg) = &res[0];
+        assert_eq!(user.route, "/id");
+        assert!(user.id_rule.canonical("42").is_some() && user.id_rule.canonical("abc").is_none());
+        assert_eq!(user.id_rule.canonical("042").as_deref(), Some("42"));
+        assert_eq!(cfg.attr_groups.len(), 1);
+        assert_eq!(cfg.request_timeout_ms, Some(500));
+
+        let (org, cfg) = &res[1];
+        assert_eq!(org.route, "/org");
+        assert!(org.id_rule.canonical("67e55044-10b1-426f-9247-bb680e5fe0c8").is_some() && org.id_rule.canonical("42").is_none());
+        assert_eq!(org.id_rule.canonical("67E55044-10B1-426F-9247-BB680E5FE0C8").as_deref(), Some("67e55044-10b1-426f-9247-bb680e5fe0c8"));
+        assert_eq!(cfg.attr_groups.len(), 2);
+        assert_eq!(cfg.request_timeout_ms, Some(100));
+
+        let (account, _) = &res[2];
+        assert!(account.id_rule.canonical("acc-7").is_some() && account.id_rule.canonical("xacc-7").is_none());
+
+        let legacy = super::parse_entities(r#"{"attributes": []}"#.as_bytes()).unwrap();
+        assert_eq!(legacy[0].0.route, "/id");
+
+        let stray = r#"{"attributes": [], "entities": [{"name": "user", "sets": {}}]}"#;
+        assert!(super::parse_entities(stray.as_bytes()).is_err());
+        let duplicate = r#"{"entities": [{"name": "user", "sets": {}}, {"name": "org", "route": "/user", "sets": {}}]}"#;
+        assert!(super::parse_entities(duplicate.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn parse_params() {
+        let data = r#"{
+"entities": [
+    {
+        "name": "user",
+        "route": "/t/{tenant}/users",
+        "params": {
+            "tenant": {"from": "path", "type": "string"},
+            "as_of": {"from": "query", "type": "date", "default": "2024-01-31"},
+            "region": {"from": "header", "name": "X-Region"}
+        },
+        "sets": {}
+    }
+]}"#.as_bytes();
+        let res = super::parse_entities(data).unwrap();
+        let params = &res[0].1.params;
+        assert_eq!(params.len(), 3);
+
+        let as_of = params.iter().find(|p| p.name == "as_of").unwrap();
+        assert_eq!(as_of.source, ParamSource::Query(String::from("as_of")));
+        assert_eq!(as_of.ty, ParamType::Date);
+        assert_eq!(as_of.default.as_deref(), Some("2024-01-31"));
+
+        let region = params.iter().find(|p| p.name == "region").unwrap();
+        assert_eq!(region.source, ParamSource::Header(String::from("X-Region")));
+        assert_eq!(region.ty, ParamType::String);
+
+        let bad_default = r#"{"params": {"n": {"from": "query", "type": "integer", "default": "x"}}}"#;
+        assert!(super::parse_entities(bad_default.as_bytes()).is_err());
+        let no_segment = r#"{"params": {"tenant": {"from": "path"}}}"#;
+        assert!(super::parse_entities(no_segment.as_bytes()).is_err());
+
+        assert!(super::check_param(ParamType::Date, "2024-02-30").is_ok());
+        assert!(super::check_param(ParamType::Date, "2024-13-01").is_err());
+        assert!(super::check_param(ParamType::Boolean, "yes").is_err());
+    }
+
+    #[test]
+    fn parse_inputs() {
+        let data = r#"{
+"users": [
+    {"connection": "postgres", "query": "select org_id from users where id = :id", "select_attributes": {"org_id": ["Type::String"]}}
+],
+"orgs": [
+    {"connection": "mysql", "query": "select name from orgs where id = :org", "inputs": {"org": "users.org_id"}, "select_attributes": {"name": ["Type::String", "!ConvertName::org"]}},
+    {"connection": "mysql", "query": "select plan from plans where org = :org", "inputs": {"org": "orgs.org"}, "select_attributes": {"plan": ["Type::String"]}}
+]}"#;
+        let cfg = super::parse(data.as_bytes()).unwrap();
+        let (_, orgs) = cfg.attr_groups.iter().find(|(k, _)| k == "orgs").unwrap();
+        assert_eq!(orgs[0].inputs[0].name, "org");
+        assert_eq!((orgs[0].inputs[0].set.as_str(), orgs[0].inputs[0].attr.as_str()), ("users", "org_id"));
+
+        let levels = super::group_levels(&cfg).unwrap();
+        let level = |set: &str| &levels[cfg.attr_groups.iter().position(|(k, _)| k == set).unwrap()];
+        assert_eq!(level("users"), &vec![0]);
+        assert_eq!(level("orgs"), &vec![1, 2]);
+
+        let cycle = r#"{
+"a": [{"query": "select x from t where y = :y", "inputs": {"y": "b.y"}, "select_attributes": {"x": ["Type::String"]}}],
+"b": [{"query": "select y from t where x = :x", "inputs": {"x": "a.x"}, "select_attributes": {"y": ["Type::String"]}}]
+}"#;
+        let err = super::parse(cycle.as_bytes()).err().unwrap().to_string();
+        assert!(err.starts_with("dependency cycle"), "{}", err);
+
+        let unknown = r#"{"a": [{"query": "q", "inputs": {"y": "b.y"}, "select_attributes": {"x": ["Type::String"]}}]}"#;
+        assert!(super::parse(unknown.as_bytes()).is_err());
+        let multiple = r#"{
+"a": [{"query": "q", "expected_rows": "multiple", "select_attributes": {"x": ["Type::String"]}}],
+"b": [{"query": "q", "inputs": {"x": "a.x"}, "select_attributes": {"y": ["Type::String"]}}]
+}"#;
+        assert!(super::parse(multiple.as_bytes()).is_err());
+        let merged = r#"{
+"a": [
+    {"query": "q", "select_attributes": {"x": ["Type::String", "!Merge::first_non_null"]}},
+    {"query": "q", "select_attributes": {"x": ["Type::String"]}}
+],
+"b": [{"query": "q", "inputs": {"x": "a.x"}, "select_attributes": {"y": ["Type::String"]}}]
+}"#;
+        let err = super::parse(merged.as_bytes()).err().unwrap().to_string();
+        assert_eq!(err, "input x of group 0 of b reads a.x that is merged from several groups");
+        let clash = r#"{"a": [{"query": "q", "select_attributes": {"x": ["Type::String"]}}], "b": [{"query": "q", "inputs": {"id": "a.x"}, "select_attributes": {"y": ["Type::String"]}}]}"#;
+        assert!(super::parse(clash.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn parse_children() {
+        let data = r#"{"orgs": [{"query": "q", "expected_rows": "multiple", "name": "orgs", "select_attributes": {"name": ["Type::String"]},
+            "children": [{"query": "c", "key_column": "org_id", "parent_column": "id", "select_attributes": {"email": ["Type::String"]}}]}]}"#;
+        let cfg = super::parse(data.as_bytes()).unwrap();
+        let group = &cfg.attr_groups[0].1[0];
+        assert_eq!(group.outputs(), vec!["orgs"]);
+        assert_eq!(group.children[0].key_column.as_deref(), Some("org_id"));
+        assert_eq!(group.children[0].parent_column.as_deref(), Some("id"));
+
+        let unnamed = data.replace(r#""name": "orgs", "#, "");
+        assert!(super::parse(unnamed.as_bytes()).is_err());
+        let single = data.replace(r#""expected_rows": "multiple", "#, "");
+        assert!(super::parse(single.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn parse_merge() {
+        let data = r#"{"a": [
+            {"query": "q", "select_attributes": {"x": ["Type::String", "!Merge::prefer:mysql"]}},
+            {"query": "q", "select_attributes": {"y": ["Type::String", "!ConvertName::x"]}}
+        ]}"#;
+        let cfg = super::parse(data.as_bytes()).unwrap();
+        assert_eq!(super::merge_rule(&cfg, "a", "x"), Some(&super::Merge::Prefer(Connection::MySQL)));
+
+        let unresolved = data.replace(r#", "!Merge::prefer:mysql""#, "");
+        let err = super::parse(unresolved.as_bytes()).err().unwrap().to_string();
+        assert_eq!(err, "x of a is produced by groups 0, 1 without a !Merge strategy");
+        let conflicting = data.replace(r#""!ConvertName::x""#, r#""!ConvertName::x", "!Merge::error""#);
+        assert!(super::parse(conflicting.as_bytes()).is_err());
+        let twice = r#"{"a": [{"query": "q", "select_attributes": {"x": ["Type::String"], "y": ["Type::String", "!ConvertName::x"]}}]}"#;
+        assert!(super::parse(twice.as_bytes()).is_err());
+        let unknown = data.replace("prefer:mysql", "latest");
+        assert!(super::parse(unknown.as_bytes()).is_err());
+
+        let nested = r#"{"a": [{"query": "q", "select_attributes": {"x": ["Type::String", "!ConvertName::addr.city"], "y": ["Type::String", "!ConvertName::addr.zip"]}}]}"#;
+        assert!(super::parse(nested.as_bytes()).is_ok());
+        assert!(super::parse(nested.replace("addr.zip", "addr").as_bytes()).is_err());
+        assert!(super::parse(nested.replace("addr.zip", "addr.").as_bytes()).is_err());
+    }
+
+    #[test]
+    fn parse_modifiers() {
+        let attrs = |props: &str| format!(r#"{{"a": [{{"query": "q", "select_attributes": {{"x": ["Type::String", {}]}}}}]}}"#, props);
+
+        let cfg = super::parse(attrs(r#""!Trim", "!Regex::[^0-9]+::", "!Default::0", "!Split::,", "!DateFormat::%d.%m.%Y""#).as_bytes()).unwrap();
+        let modifiers = &cfg.attr_groups[0].1[0].select_attrs[0].1.modifiers;
+        assert_eq!(modifiers.len(), 5);
+        assert!(matches!(&modifiers[1], super::Modifier::Regex(re, r) if re.as_str() == "[^0-9]+" && r.is_empty()));
+
+        let cfg = super::parse(attrs(r#""!Mask::last4", "!Hash::sha256", "!Redact", "!UnmaskFor::admin, support""#).as_bytes()).unwrap();
+        let props = &cfg.attr_groups[0].1[0].select_attrs[0].1;
+        assert!(props.modifiers.iter().all(super::Modifier::masks));
+        assert_eq!(props.unmask_for, vec!["admin", "support"]);
+        assert!(cfg.attr_groups[0].1[0].masks());
+
+        let cfg = super::parse(br#"{"masking": {"header": "X-Role", "unmasked": ["admin"], "trust_role_header": true}}"#).unwrap();
+        assert_eq!(cfg.masking.as_ref().unwrap().unmasked, vec!["admin"]);
+        assert!(cfg.masking.unwrap().trust_role_header);
+        assert!(super::parse(br#"{"masking": {"unmasked": ["admin"], "trust_role_header": true}}"#).is_err());
+        assert!(super::parse(br#"{"masking": {"header": "X-Role", "unmasked": ["admin"]}}"#).is_err());
+        assert!(super::parse(br#"{"masking": {"header": "X-Role", "trust_role_header": true, "tokens": {"2bb80d537b1da3e38bd30361aa855686bde0eacd7162fef6a25fe97bf527a25b": "admin"}}}"#).is_err());
+        let cfg = super::parse(br#"{"masking": {"header": "Authorization", "tokens": {"2BB80D537B1DA3E38BD30361AA855686BDE0EACD7162FEF6A25FE97BF527A25B": "admin"}}}"#).unwrap();
+        assert_eq!(cfg.masking.unwrap().tokens, vec![(String::from("2bb80d537b1da3e38bd30361aa855686bde0eacd7162fef6a25fe97bf527a25b"), String::from("admin"))]);
+        assert!(super::parse(br#"{"masking": {"header": "Authorization", "tokens": {"secret": "admin"}}}"#).is_err());
+
+        let cfg = super::parse(br#"{"admin_tokens": ["2BB80D537B1DA3E38BD30361AA855686BDE0EACD7162FEF6A25FE97BF527A25B"]}"#).unwrap();
+        assert_eq!(cfg.admin_tokens, vec![String::from("2bb80d537b1da3e38bd30361aa855686bde0eacd7162fef6a25fe97bf527a25b")]);
+        assert!(super::parse(br#"{"admin_tokens": ["secret"]}"#).is_err());
+
+        for invalid in [r#""!Mask::phone""#, r#""!Hash::md5""#, r#""!Regex::(""#, r#""!Regex::a(::b""#, r#""!Split::""#, r#""!DateFormat::%Q""#, r#""!DateFormat::%""#] {
+            assert!(super::parse(attrs(invalid).as_bytes()).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn parse_computed() {
+        let set = |computed: &str| format!(r#"{{"a": [
+            {{"query": "q", "select_attributes": {{"fn": ["Type::String"], "age": ["Type::Number"], "tags": ["Type::String", "!Split::,"]}}}},
+            {{"query": "q", "expected_rows": "multiple", "select_attributes": {{"name": ["Type::String", "!ConvertName::names"]}}}},
+            {{"computed": {{{}}}}}
+        ]}}"#, computed);
+
+        let cfg = super::parse(set(r#""b": ["Type::String", "!Expr::'hi ' + a"], "a": ["!Expr::fn + ' ' + age", "Type::String"]"#).as_bytes()).unwrap();
+        let computed = cfg.computed("a");
+        assert_eq!(cfg.attr_groups[0].1.len(), 2);
+        assert_eq!(computed.iter().map(|c| c.name.as_str()).collect::<Vec<_>>(), vec!["a", "b"]);
+        assert_eq!(computed[1].sources, vec!["age", "fn"]);
+        assert_eq!(computed[1].ty, Type::String);
+
+        for invalid in [
+            r#""b": ["Type::Number", "!Expr::fn + 1"]"#,
+            r#""b": ["Type::Boolean", "!Expr::age && true"]"#,
+            r#""b": ["Type::String", "!Expr::names"]"#,
+            r#""b": ["Type::String", "!Expr::tags"]"#,
+            r#""b": ["Type::String", "!Expr::nope"]"#,
+            r#""b": ["Type::String", "!Expr::c"], "c": ["Type::String", "!Expr::b"]"#,
+            r#""age": ["Type::Number", "!Expr::1"]"#,
+            r#""fn.x": ["Type::Number", "!Expr::1"]"#,
+            r#""b": ["Type::String"]"#,
+            r#""b": ["!Expr::fn"]"#,
+            r#""b": ["Type::String", "!Expr::fn +"]"#,
+        ] {
+            assert!(super::parse(set(invalid).as_bytes()).is_err(), "{}", invalid);
+        }
+    }
+}
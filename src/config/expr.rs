@@ -0,0 +1,326 @@
+use crate::config::config::Type;
+
+/// Expression of a computed attribute. Attributes are typed when the
+/// expression is checked against the attributes of its set.
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Str(String),
+    Num(f64),
+    Bool(bool),
+    Attr(String, Type),
+    Not(Box<Expr>),
+    Neg(Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+
+/// A value an expression evaluates to.
+#[derive(PartialEq, Debug, Clone)]
+pub enum Lit {
+    Str(String),
+    Num(f64),
+    Bool(bool),
+}
+
+impl Lit {
+    /// Reads a fetched value as `ty`, `None` when it is not one.
+    pub fn read(ty: Type, value: &str) -> Option<Self> {
+        match ty {
+            Type::Number => value.trim().parse().ok().map(Lit::Num),
+            Type::Boolean => match value {
+                "true" | "1" => Some(Lit::Bool(true)),
+                "false" | "0" => Some(Lit::Bool(false)),
+                _ => None,
+            },
+            Type::String | Type::JSON => Some(Lit::Str(value.to_string())),
+        }
+    }
+}
+
+impl std::fmt::Display for Lit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Lit::Str(s) => write!(f, "{}", s),
+            Lit::Num(n) if n.fract() == 0.0 && n.abs() < 1e15 => write!(f, "{}", *n as i64),
+            Lit::Num(n) => write!(f, "{}", n),
+            Lit::Bool(b) => write!(f, "{}", b),
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+enum Token {
+    Str(String),
+    Num(f64),
+    Ident(String),
+    Op(&'static str),
+}
+
+const OPS: [&str; 16] = ["==", "!=", "<=", ">=", "&&", "||", "+", "-", "*", "/", "<", ">", "!", "(", ")", "="];
+
+fn tokenize(src: &str) -> Result<Vec<Token>, String> {
+    let chars = src.chars().collect::<Vec<_>>();
+    let mut tokens = vec![];
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '\'' || c == '"' {
+            let end = (i + 1..chars.len()).find(|&j| chars[j] == c).ok_or("unterminated string")?;
+            tokens.push(Token::Str(chars[i + 1..end].iter().collect()));
+            i = end + 1;
+        } else if c.is_ascii_digit() {
+            let end = (i..chars.len()).find(|&j| !(chars[j].is_ascii_digit() || chars[j] == '.')).unwrap_or(chars.len());
+            let num = chars[i..end].iter().collect::<String>();
+            tokens.push(Token::Num(num.parse().map_err(|_| format!("invalid number {}", num))?));
+            i = end;
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let end = (i..chars.len()).find(|&j| !(chars[j].is_ascii_alphanumeric() || chars[j] == '_' || chars[j] == '.')).unwrap_or(chars.len());
+            tokens.push(Token::Ident(chars[i..end].iter().collect()));
+            i = end;
+        } else {
+            let rest = chars[i..].iter().take(2).collect::<String>();
+            let op = OPS.iter().find(|op| rest.starts_with(*op)).filter(|op| **op != "=").ok_or_else(|| format!("unexpected {}", c))?;
+            tokens.push(Token::Op(op));
+            i += op.len();
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Parses an expression of literals (`'text'`, `"text"`, `42`, `1.5`, `true`,
+/// `false`), attribute names, parentheses and, from the lowest precedence up,
+/// `||`, `&&`, `==` `!=`, `<` `<=` `>` `>=`, `+` `-`, `*` `/` and unary `!` `-`.
+pub fn parse(src: &str) -> Result<Expr, String> {
+    let tokens = tokenize(src)?;
+    let mut parser = Parser { tokens, pos: 0 };
+    let expr = parser.binary(0)?;
+    match parser.tokens.get(parser.pos) {
+        None => Ok(expr),
+        Some(t) => Err(format!("unexpected {:?}", t)),
+    }
+}
+
+/// Binary operators by precedence level, lowest first.
+const LEVELS: [&[(&str, Op)]; 6] = [
+    &[("||", Op::Or)],
+    &[("&&", Op::And)],
+    &[("==", Op::Eq), ("!=", Op::Ne)],
+    &[("<", Op::Lt), ("<=", Op::Le), (">", Op::Gt), (">=", Op::Ge)],
+    &[("+", Op::Add), ("-", Op::Sub)],
+    &[("*", Op::Mul), ("/", Op::Div)],
+];
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let t = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        t
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, String> {
+        if level == LEVELS.len() {
+            return self.unary()
+        }
+
+        let mut lhs = self.binary(level + 1)?;
+        loop {
+            let op = match self.tokens.get(self.pos) {
+                Some(Token::Op(s)) => LEVELS[level].iter().find(|(o, _)| o == s).map(|(_, op)| *op),
+                _ => None,
+            };
+            match op {
+                Some(op) => {
+                    self.pos += 1;
+                    lhs = Expr::Binary(op, Box::new(lhs), Box::new(self.binary(level + 1)?));
+                }
+                None => return Ok(lhs),
+            }
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Op("!")) => Ok(Expr::Not(Box::new(self.unary()?))),
+            Some(Token::Op("-")) => Ok(Expr::Neg(Box::new(self.unary()?))),
+            Some(Token::Op("(")) => {
+                let expr = self.binary(0)?;
+                match self.next() {
+                    Some(Token::Op(")")) => Ok(expr),
+                    _ => Err(String::from("missing )")),
+                }
+            }
+            Some(Token::Str(s)) => Ok(Expr::Str(s)),
+            Some(Token::Num(n)) => Ok(Expr::Num(n)),
+            Some(Token::Ident(id)) => Ok(match id.as_str() {
+                "true" => Expr::Bool(true),
+                "false" => Expr::Bool(false),
+                _ => Expr::Attr(id, Type::String),
+            }),
+            Some(t) => Err(format!("unexpected {:?}", t)),
+            None => Err(String::from("unexpected end of expression")),
+        }
+    }
+}
+
+impl Expr {
+    /// Names of the attributes the expression reads.
+    pub fn attrs(&self) -> Vec<&str> {
+        match self {
+            Expr::Attr(name, _) => vec![name],
+            Expr::Not(e) | Expr::Neg(e) => e.attrs(),
+            Expr::Binary(_, l, r) => [l.attrs(), r.attrs()].concat(),
+            _ => vec![],
+        }
+    }
+
+    /// Types every attribute with `type_of` and returns the type of the expression.
+    /// `+` adds numbers and concatenates when either side is a string.
+    pub fn check(&mut self, type_of: &dyn Fn(&str) -> Result<Type, String>) -> Result<Type, String> {
+        let ty = match self {
+            Expr::Str(_) => Type::String,
+            Expr::Num(_) => Type::Number,
+            Expr::Bool(_) => Type::Boolean,
+            Expr::Attr(name, ty) => {
+                *ty = match type_of(name)? {
+                    Type::JSON => Type::String,
+                    t => t,
+                };
+                *ty
+            }
+            Expr::Not(e) => match e.check(type_of)? {
+                Type::Boolean => Type::Boolean,
+                _ => return Err(String::from("! needs a boolean")),
+            },
+            Expr::Neg(e) => match e.check(type_of)? {
+                Type::Number => Type::Number,
+                _ => return Err(String::from("- needs a number")),
+            },
+            Expr::Binary(op, l, r) => {
+                let (l, r) = (l.check(type_of)?, r.check(type_of)?);
+                match (op, l, r) {
+                    (Op::Add, Type::String, _) | (Op::Add, _, Type::String) if l != Type::Boolean && r != Type::Boolean => Type::String,
+                    (Op::Add | Op::Sub | Op::Mul | Op::Div, Type::Number, Type::Number) => Type::Number,
+                    (Op::Eq | Op::Ne, l, r) if l == r => Type::Boolean,
+                    (Op::Lt | Op::Le | Op::Gt | Op::Ge, Type::Number, Type::Number) => Type::Boolean,
+                    (Op::Lt | Op::Le | Op::Gt | Op::Ge, Type::String, Type::String) => Type::Boolean,
+                    (Op::And | Op::Or, Type::Boolean, Type::Boolean) => Type::Boolean,
+                    (op, l, r) => return Err(format!("{:?} cannot combine {} and {}", op, l.name(), r.name())),
+                }
+            }
+        };
+
+        Ok(ty)
+    }
+
+    /// Evaluates a checked expression. `None` when an attribute it reads is
+    /// missing or not of its type, or on a division by zero.
+    pub fn eval(&self, value_of: &dyn Fn(&str, Type) -> Option<Lit>) -> Option<Lit> {
+        let r = match self {
+            Expr::Str(s) => Lit::Str(s.to_string()),
+            Expr::Num(n) => Lit::Num(*n),
+            Expr::Bool(b) => Lit::Bool(*b),
+            Expr::Attr(name, ty) => value_of(name, *ty)?,
+            Expr::Not(e) => match e.eval(value_of)? {
+                Lit::Bool(b) => Lit::Bool(!b),
+                _ => return None,
+            },
+            Expr::Neg(e) => match e.eval(value_of)? {
+                Lit::Num(n) => Lit::Num(-n),
+                _ => return None,
+            },
+            Expr::Binary(op, l, r) => match (op, l.eval(value_of)?, r.eval(value_of)?) {
+                (Op::Add, Lit::Num(a), Lit::Num(b)) => Lit::Num(a + b),
+                (Op::Add, a, b) => Lit::Str(format!("{}{}", a, b)),
+                (Op::Sub, Lit::Num(a), Lit::Num(b)) => Lit::Num(a - b),
+                (Op::Mul, Lit::Num(a), Lit::Num(b)) => Lit::Num(a * b),
+                (Op::Div, Lit::Num(_), Lit::Num(0.0)) => return None,
+                (Op::Div, Lit::Num(a), Lit::Num(b)) => Lit::Num(a / b),
+                (Op::Eq, a, b) => Lit::Bool(a == b),
+                (Op::Ne, a, b) => Lit::Bool(a != b),
+                (Op::Lt | Op::Le | Op::Gt | Op::Ge, a, b) => {
+                    let ord = match (a, b) {
+                        (Lit::Num(a), Lit::Num(b)) => a.partial_cmp(&b)?,
+                        (Lit::Str(a), Lit::Str(b)) => a.cmp(&b),
+                        _ => return None,
+                    };
+                    Lit::Bool(match op {
+                        Op::Lt => ord.is_lt(),
+                        Op::Le => ord.is_le(),
+                        Op::Gt => ord.is_gt(),
+                        _ => ord.is_ge(),
+                    })
+                }
+                (Op::And, Lit::Bool(a), Lit::Bool(b)) => Lit::Bool(a && b),
+                (Op::Or, Lit::Bool(a), Lit::Bool(b)) => Lit::Bool(a || b),
+                _ => return None,
+            },
+        };
+
+        Some(r)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::config::Type;
+    use crate::config::expr::{parse, Lit};
+
+    fn eval(src: &str) -> Result<Option<Lit>, String> {
+        let mut expr = parse(src)?;
+        let type_of = |name: &str| match name {
+            "firstname" | "lastname" => Ok(Type::String),
+            "age" => Ok(Type::Number),
+            "active" => Ok(Type::Boolean),
+            _ => Err(format!("unknown attribute {}", name)),
+        };
+        expr.check(&type_of)?;
+        Ok(expr.eval(&|name, ty| match name {
+            "firstname" => Lit::read(ty, "Islam"),
+            "lastname" => Lit::read(ty, "Ahmed"),
+            "age" => Lit::read(ty, "30"),
+            "active" => Lit::read(ty, "1"),
+            _ => None,
+        }))
+    }
+
+    #[test]
+    fn expressions() {
+        assert_eq!(eval(r#"firstname + " " + lastname"#), Ok(Some(Lit::Str(String::from("Islam Ahmed")))));
+        assert_eq!(eval("age >= 18 && active"), Ok(Some(Lit::Bool(true))));
+        assert_eq!(eval("(age + 2) * 2 / 4"), Ok(Some(Lit::Num(16.0))));
+        assert_eq!(eval("'age: ' + age"), Ok(Some(Lit::Str(String::from("age: 30")))));
+        assert_eq!(eval("!(age < 18) == true"), Ok(Some(Lit::Bool(true))));
+        assert_eq!(eval("-age + 1"), Ok(Some(Lit::Num(-29.0))));
+        assert_eq!(eval("age / 0"), Ok(None));
+        assert_eq!(Lit::Num(2.5).to_string(), "2.5");
+        assert_eq!(Lit::Num(3.0).to_string(), "3");
+
+        for invalid in ["age &&", "firstname - 1", "active + 1", "age == 'x'", "nope", "(age", "age = 1", "'open", "1.2.3"] {
+            assert!(eval(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use lru::LruCache;
+use crate::config::config::Connection;
+use crate::storage::connection::Row;
+
+#[derive(Hash, Eq, PartialEq, Clone)]
+pub struct CacheKey {
+    pub conn: Connection,
+    pub query: String,
+    pub params: Vec<(String, String)>,
+}
+
+struct Entry {
+    rows: Vec<Row>,
+    expires: Instant,
+    /// Entity ids the rows were fetched for.
+    ids: Vec<String>,
+}
+
+/// Bounded LRU of query results. Entries expire after the TTL they were stored with.
+pub struct ResultCache {
+    entries: Mutex<LruCache<CacheKey, Entry>>
+}
+
+impl ResultCache {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN);
+        Self {
+            entries: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+
+    pub fn get(&self, key: &CacheKey) -> Option<Vec<Row>> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some(e) if e.expires > Instant::now() => Some(e.rows.clone()),
+            Some(_) => {
+                entries.pop(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn put(&self, key: CacheKey, rows: Vec<Row>, ttl: Duration, ids: Vec<String>) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.put(key, Entry { rows, expires: Instant::now() + ttl, ids });
+    }
+
+    /// Drops every entry that was fetched for entity `id`.
+    pub fn evict(&self, id: &str) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let keys = entries.iter()
+            .filter(|(_, e)| e.ids.iter().any(|i| i == id))
+            .map(|(k, _)| k.clone())
+            .collect::<Vec<_>>();
+
+        for k in keys.iter() {
+            entries.pop(k);
+        }
+
+        keys.len()
+    }
+
+    pub fn flush(&self) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let n = entries.len();
+        entries.clear();
+        n
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread::sleep;
+    use std::time::Duration;
+    use crate::config::config::Connection::PostgresSQL;
+    use crate::domain::cache::{CacheKey, ResultCache};
+    use crate::storage::connection::Row;
+
+    fn key(id: &str) -> CacheKey {
+        CacheKey {
+            conn: PostgresSQL,
+            query: format!("select * from users where id = '{}'", id),
+            params: vec![(String::from("__PID__"), id.to_string())],
+        }
+    }
+
+    fn rows() -> Vec<Row> {
+        vec![Row { columns: vec![(String::from("id"), String::from("1"))] }]
+    }
+
+    #[test]
+    fn get_put() {
+        let cache = ResultCache::new(2);
+        cache.put(key("1"), rows(), Duration::from_secs(60), vec![]);
+        cache.put(key("2"), rows(), Duration::from_millis(10), vec![]);
+        assert!(cache.get(&key("1")).is_some());
+
+        sleep(Duration::from_millis(20));
+        assert!(cache.get(&key("2")).is_none(), "expired");
+
+        cache.put(key("3"), rows(), Duration::from_secs(60), vec![]);
+        cache.put(key("4"), rows(), Duration::from_secs(60), vec![]);
+        assert!(cache.get(&key("1")).is_none(), "least recently used is dropped");
+    }
+
+    #[test]
+    fn evict_flush() {
+        let cache = ResultCache::new(10);
+        cache.put(key("1"), rows(), Duration::from_secs(60), vec![String::from("1")]);
+        cache.put(key("2"), rows(), Duration::from_secs(60), vec![String::from("2")]);
+        // fetched for another id with a param equal to the evicted one
+        let mut tenant = key("3");
+        tenant.params.push((String::from(":tenant"), String::from("1")));
+        cache.put(tenant.clone(), rows(), Duration::from_secs(60), vec![String::from("3")]);
+        cache.put(key("4"), rows(), Duration::from_secs(60), vec![String::from("4"), String::from("1")]);
+
+        assert_eq!(cache.evict("1"), 2);
+        assert!(cache.get(&key("1")).is_none());
+        assert!(cache.get(&key("4")).is_none());
+        assert!(cache.get(&key("2")).is_some());
+        assert!(cache.get(&tenant).is_some());
+
+        assert_eq!(cache.flush(), 2);
+        assert!(cache.get(&key("2")).is_none());
+    }
+}
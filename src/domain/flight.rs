@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hash;
+use std::sync::Mutex;
+use tokio::sync::watch;
+
+/// Coalesces concurrent calls with the same key: the first caller runs the
+/// future and everybody who arrives while it is in flight gets a clone of
+/// its result. If the first caller is dropped before finishing, one of the
+/// waiting callers takes over and runs its own future for the others.
+pub struct SingleFlight<K, V> {
+    calls: Mutex<HashMap<K, watch::Receiver<Option<V>>>>
+}
+
+/// Removes the key once the leading call finishes or is dropped, before its
+/// sender goes away, so a waiting caller can take over.
+struct Leader<'a, K: Hash + Eq, V> {
+    flight: &'a SingleFlight<K, V>,
+    key: K,
+}
+
+impl<K: Hash + Eq, V> Drop for Leader<'_, K, V> {
+    fn drop(&mut self) {
+        self.flight.calls.lock().unwrap().remove(&self.key);
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> SingleFlight<K, V> {
+    pub fn new() -> Self {
+        Self {
+            calls: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn run<F, Fut>(&self, key: K, f: F) -> V
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = V>,
+    {
+        loop {
+            let waiting = {
+                let mut calls = self.calls.lock().unwrap();
+                match calls.get(&key) {
+                    Some(rx) => Err(rx.clone()),
+                    None => {
+                        let (tx, rx) = watch::channel(None);
+                        calls.insert(key.clone(), rx);
+                        Ok(tx)
+                    }
+                }
+            };
+
+            match waiting {
+                Ok(tx) => {
+                    let _leader = Leader { flight: self, key };
+                    let v = f().await;
+                    let _ = tx.send(Some(v.clone()));
+                    return v
+                }
+                // the leader was dropped without a result, the first to get here leads next
+                Err(mut rx) => if let Ok(v) = rx.wait_for(Option::is_some).await {
+                    return v.clone().expect("value is set")
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use futures::future::join_all;
+    use crate::domain::flight::SingleFlight;
+
+    #[tokio::test]
+    async fn coalesce() {
+        let flight = SingleFlight::new();
+        let calls = AtomicUsize::new(0);
+
+        let futs = (0..5).map(|_| flight.run("42", || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            7
+        }));
+
+        assert_eq!(join_all(futs).await, vec![7; 5]);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        assert_eq!(flight.run("42", || async { 8 }).await, 8, "finished calls are not reused");
+    }
+
+    #[tokio::test]
+    async fn leader_dropped() {
+        let flight = SingleFlight::new();
+
+        let leader = flight.run("42", || async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            7
+        });
+        let follower = flight.run("42", || async { 8 });
+
+        let leader = tokio::time::timeout(Duration::from_millis(10), leader);
+        let (l, f) = futures::join!(leader, follower);
+        assert!(l.is_err());
+        assert_eq!(f, 8);
+    }
+
+    #[tokio::test]
+    async fn leader_dropped_promotes_one() {
+        let flight = SingleFlight::new();
+        let calls = AtomicUsize::new(0);
+
+        let leader = flight.run("42", || async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            7
+        });
+        let followers = join_all((0..4).map(|i| flight.run("42", {
+            let calls = &calls;
+            move || async move {
+                calls.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                i
+            }
+        })));
+
+        let leader = tokio::time::timeout(Duration::from_millis(10), leader);
+        let (l, f) = futures::join!(leader, followers);
+        assert!(l.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(f.iter().all(|v| *v == f[0]));
+    }
+}
@@ -1 +1,4 @@
-pub mod fetcher;
\ No newline at end of file
+pub mod fetcher;
+pub mod cache;
+pub mod flight;
+pub mod transform;
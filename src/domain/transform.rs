@@ -0,0 +1,126 @@
+use sha2::{Digest, Sha256};
+use crate::config::config::Modifier;
+use crate::domain::fetcher::Value;
+
+/// Runs `modifiers` in order over a fetched column value. Modifiers after a
+/// `!Split` apply to every element of the array. Masking modifiers are
+/// skipped when `reveal` is set.
+pub fn apply(modifiers: &[Modifier], value: &str, reveal: bool) -> Value {
+    modifiers.iter().filter(|m| !(reveal && m.masks())).fold(Value::String(value.to_string()), |v, m| match v {
+        Value::String(s) => apply_one(m, s),
+        Value::Array(a) => Value::Array(a.into_iter().flat_map(|s| match apply_one(m, s) {
+            Value::Array(a) => a,
+            Value::String(s) => vec![s],
+            _ => vec![],
+        }).collect()),
+        v => v,
+    })
+}
+
+fn apply_one(m: &Modifier, s: String) -> Value {
+    let s = match m {
+        Modifier::Trim => s.trim().to_string(),
+        Modifier::Lower => s.to_lowercase(),
+        Modifier::Upper => s.to_uppercase(),
+        Modifier::Regex(re, replacement) => re.replace_all(&s, replacement.as_str()).into_owned(),
+        Modifier::DateFormat(fmt) => format_date(&s, fmt).unwrap_or(s),
+        Modifier::Default(default) if s.is_empty() || s == "null" => default.to_string(),
+        Modifier::Default(_) => s,
+        Modifier::Split(_) if s.is_empty() => return Value::Array(vec![]),
+        Modifier::Split(sep) => return Value::Array(s.split(sep.as_str()).map(String::from).collect()),
+        Modifier::MaskEmail => match s.split_once('@') {
+            Some((user, domain)) => format!("{}***@{}", user.chars().next().unwrap_or('*'), domain),
+            None => String::from("***"),
+        },
+        Modifier::MaskLast4 => {
+            let n = s.chars().count();
+            s.chars().enumerate().map(|(i, c)| if n > 4 && i >= n - 4 { c } else { '*' }).collect()
+        }
+        Modifier::Sha256 => format!("{:x}", Sha256::digest(s.as_bytes())),
+        Modifier::Redact => String::from("[redacted]"),
+    };
+
+    Value::String(s)
+}
+
+/// Formats a `YYYY-MM-DD` date, optionally followed by ` HH:MM:SS` or
+/// `THH:MM:SS`, with the `%` specifiers of `config::DATE_SPECIFIERS`.
+/// Returns `None` for anything else.
+fn format_date(s: &str, fmt: &str) -> Option<String> {
+    let num = |from: usize, to: usize| s.get(from..to).filter(|n| n.bytes().all(|b| b.is_ascii_digit())).and_then(|n| n.parse::<u32>().ok());
+    if s.get(4..5) != Some("-") || s.get(7..8) != Some("-") {
+        return None
+    }
+    let (y, mo, d) = (num(0, 4)?, num(5, 7)?, num(8, 10)?);
+    let (h, mi, sec) = match s.get(10..11) {
+        None => (0, 0, 0),
+        Some("T") | Some(" ") => (num(11, 13)?, num(14, 16)?, num(17, 19)?),
+        Some(_) => return None,
+    };
+
+    let mut out = String::new();
+    let mut chars = fmt.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue
+        }
+        let part = match chars.next()? {
+            'Y' => format!("{:04}", y),
+            'y' => format!("{:02}", y % 100),
+            'm' => format!("{:02}", mo),
+            'd' => format!("{:02}", d),
+            'H' => format!("{:02}", h),
+            'M' => format!("{:02}", mi),
+            'S' => format!("{:02}", sec),
+            '%' => String::from("%"),
+            _ => return None,
+        };
+        out.push_str(&part);
+    }
+
+    Some(out)
+}
+
+#[cfg(test)]
+mod test {
+    use regex::Regex;
+    use crate::config::config::Modifier;
+    use crate::domain::fetcher::Value;
+
+    fn apply(modifiers: &[Modifier], value: &str) -> Vec<String> {
+        match super::apply(modifiers, value, false) {
+            Value::String(s) => vec![s],
+            Value::Array(a) => a,
+            _ => panic!("unexpected value"),
+        }
+    }
+
+    #[test]
+    fn modifiers() {
+        assert_eq!(apply(&[Modifier::Trim, Modifier::Upper], "  islam "), vec!["ISLAM"]);
+        assert_eq!(apply(&[Modifier::Lower], "ACME"), vec!["acme"]);
+        assert_eq!(apply(&[Modifier::Regex(Regex::new(r"\D").unwrap(), String::new())], "+1 (555) 01"), vec!["155501"]);
+        assert_eq!(apply(&[Modifier::Default(String::from("n/a"))], "null"), vec!["n/a"]);
+        assert_eq!(apply(&[Modifier::Default(String::from("n/a"))], "x"), vec!["x"]);
+        assert_eq!(apply(&[Modifier::Split(String::from(",")), Modifier::Trim], "a, b ,c"), vec!["a", "b", "c"]);
+        assert_eq!(apply(&[Modifier::Split(String::from(","))], ""), Vec::<String>::new());
+
+        let date = |fmt: &str, value: &str| apply(&[Modifier::DateFormat(fmt.to_string())], value).remove(0);
+        assert_eq!(date("%d.%m.%Y", "2024-01-31"), "31.01.2024");
+        assert_eq!(date("%y%m%d %H:%M", "2024-01-31T08:05:09"), "240131 08:05");
+        assert_eq!(date("%d.%m.%Y", "yesterday"), "yesterday");
+    }
+
+    #[test]
+    fn masking() {
+        assert_eq!(apply(&[Modifier::MaskEmail], "islam@example.com"), vec!["i***@example.com"]);
+        assert_eq!(apply(&[Modifier::MaskEmail], "nobody"), vec!["***"]);
+        assert_eq!(apply(&[Modifier::MaskLast4], "+15550123"), vec!["*****0123"]);
+        assert_eq!(apply(&[Modifier::MaskLast4], "123"), vec!["***"]);
+        assert_eq!(apply(&[Modifier::Sha256], "abc"), vec!["ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"]);
+        assert_eq!(apply(&[Modifier::Trim, Modifier::Redact], " x "), vec!["[redacted]"]);
+
+        assert!(matches!(super::apply(&[Modifier::Trim, Modifier::Redact], " x ", true), Value::String(v) if v == "x"));
+    }
+}
@@ -1,40 +1,253 @@
-use actix_web::{FromRequest, Handler, HttpRequest, HttpResponse, Responder, web};
+use std::collections::HashMap;
+use std::time::Duration;
+use actix_web::{HttpRequest, HttpResponse, web};
+use actix_web::http::header;
 use actix_web::web::ServiceConfig;
+use futures_util::StreamExt;
+use crate::config::config::ParamSource;
+use crate::domain::fetcher::{Error, Fields, Lookup, Params};
+use crate::http::handlers::{EntityHandler, batch_to_json, error_message, explain_to_json, export_line, fetched_to_json, readiness_to_json, set_to_json, sets_to_json};
 use serde_json::json;
-use crate::domain::fetcher::Value;
 use crate::http::server::State;
+use crate::metrics::metrics::metrics;
 
-pub fn route_factory(cfg: &mut ServiceConfig) {
-        cfg.route("/id/{id}", web::get().to(handle));
+/// Registers `{route}/{id}` and `{route}/{id}/{set}` for every entity route, in
+/// the order of `State::entities`, and the endpoints shared by all entities.
+/// Those take an `?entity=` name and default to the first entity.
+pub fn route_factory(cfg: &mut ServiceConfig, routes: &[String]) {
+        for (i, route) in routes.iter().enumerate() {
+            cfg.route(&format!("{}/{{id}}", route), web::get().to(move |req: HttpRequest| handle(req, i)));
+            cfg.route(&format!("{}/{{id}}/{{set}}", route), web::get().to(move |req: HttpRequest, query| handle_set(req, query, i)));
+        }
+        cfg.route("/sets", web::get().to(handle_sets));
+        cfg.route("/ids", web::post().to(handle_batch));
+        cfg.route("/export", web::get().to(handle_export));
+        cfg.route("/metrics", web::get().to(handle_metrics));
+        cfg.route("/healthz", web::get().to(handle_healthz));
+        cfg.route("/readyz", web::get().to(handle_readyz));
+        cfg.route("/admin/cache", web::delete().to(handle_flush_cache));
+        cfg.route("/admin/cache/{id}", web::delete().to(handle_evict));
+}
+
+type Query = web::Query<HashMap<String, String>>;
+
+/// Reads the entity's named params from the path, query string and headers of `req`.
+fn bind_params(req: &HttpRequest, eh: &EntityHandler, query: &HashMap<String, String>) -> Result<Params, Error> {
+    eh.bind(|spec| match &spec.source {
+        ParamSource::Path(seg) => req.match_info().get(seg).map(String::from),
+        ParamSource::Query(k) => query.get(k).cloned(),
+        ParamSource::Header(h) => req.headers().get(h.as_str()).and_then(|v| v.to_str().ok()).map(String::from),
+    })
+}
+
+/// The caller's role, authenticated from the entity's masking header.
+fn role(req: &HttpRequest, eh: &EntityHandler) -> Option<String> {
+    eh.role_header().and_then(|h| req.headers().get(h)).and_then(|v| v.to_str().ok()).and_then(|c| eh.role(c))
+}
+
+/// Maps a failed fetch to its status: timeouts to `504`, bad fields or params to `400`.
+fn error_response(e: &Error) -> HttpResponse {
+    match e {
+        Error::Timeout(_) => HttpResponse::GatewayTimeout().body(error_message(e)),
+        Error::UnknownFields(_) | Error::InvalidParam(_) => HttpResponse::BadRequest().body(error_message(e)),
+        _ => HttpResponse::InternalServerError().body(error_message(e)),
+    }
+}
+
+/// Whether `req` sends one of the admin tokens in its `Authorization` header.
+fn is_admin(req: &HttpRequest, data: &State) -> bool {
+    let credential = req.headers().get(header::AUTHORIZATION).and_then(|v| v.to_str().ok());
+    credential.is_some_and(|c| data.entity(None).is_ok_and(|e| e.handler.admits(c)))
+}
+
+async fn handle_flush_cache(req: HttpRequest, data: web::Data<State>) -> HttpResponse {
+    if !is_admin(&req, &data) {
+        return HttpResponse::Unauthorized().body("admin token required");
+    }
+
+    let evicted: usize = data.entities.iter().map(|e| e.handler.flush_cache()).sum();
+    HttpResponse::Ok().json(json!({"evicted": evicted}))
+}
+
+async fn handle_evict(req: HttpRequest, data: web::Data<State>, id: web::Path<String>, query: Query) -> HttpResponse {
+    if !is_admin(&req, &data) {
+        return HttpResponse::Unauthorized().body("admin token required");
+    }
+
+    let entity = match data.entity(query.get("entity").map(String::as_str)) {
+        Ok(entity) => entity,
+        Err(e) => return HttpResponse::NotFound().body(e),
+    };
+
+    HttpResponse::Ok().json(json!({"evicted": entity.handler.evict(&id)}))
+}
+
+const READY_TIMEOUT: Duration = Duration::from_secs(1);
+
+async fn handle_healthz() -> HttpResponse {
+    HttpResponse::Ok().json(json!({"status": "ok"}))
+}
+
+async fn handle_readyz(data: web::Data<State>) -> HttpResponse {
+    let mut statuses = match data.entity(None) {
+        Ok(entity) => entity.handler.readiness(READY_TIMEOUT).await,
+        Err(_) => vec![],
+    };
+    // the connections are shared, but every entity has its own groups
+    for s in statuses.iter_mut() {
+        s.required = data.entities.iter().any(|e| e.handler.requires(&s.conn));
+    }
+
+    if statuses.iter().any(|s| s.required && s.status.is_err()) {
+        return HttpResponse::ServiceUnavailable().json(readiness_to_json(&statuses));
+    }
+
+    HttpResponse::Ok().json(readiness_to_json(&statuses))
+}
+
+async fn handle_metrics() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(metrics().render())
+}
+
+async fn handle_batch(req: HttpRequest, data: web::Data<State>, query: Query, body: web::Bytes) -> HttpResponse {
+    let entity = match data.entity(query.get("entity").map(String::as_str)) {
+        Ok(entity) => entity,
+        Err(e) => return HttpResponse::NotFound().body(e),
+    };
+
+    let ids: Vec<String> = match serde_json::from_slice(&body) {
+        Ok(ids) => ids,
+        Err(e) => return HttpResponse::BadRequest().body(format!("expected a json array of ids: {}", e)),
+    };
+
+    let max = entity.handler.max_batch_ids();
+    if ids.len() > max {
+        return HttpResponse::BadRequest().body(format!("batch of {} ids exceeds max_batch_ids {}", ids.len(), max));
+    }
+
+    let ids = match ids.iter().map(|id| entity.kind.id_rule.canonical(id).ok_or(id)).collect::<Result<Vec<_>, _>>() {
+        Ok(ids) => ids,
+        Err(id) => return HttpResponse::BadRequest().body(format!("invalid {} id {}", entity.kind.name, id)),
+    };
+
+    let debug = query.get("debug").is_some_and(|v| v == "true");
+    let fields = query.get("fields").map(|f| Fields::parse(f)).unwrap_or_default();
+    let params = match bind_params(&req, &entity.handler, &query) {
+        Ok(params) => params,
+        Err(e) => return HttpResponse::BadRequest().body(error_message(&e)),
+    };
+
+    let role = role(&req, &entity.handler);
+    match entity.handler.get_entities(&ids, &Lookup { fields, params, role }).await {
+        Ok(results) => HttpResponse::Ok().json(batch_to_json(results, debug)),
+        Err(e) => error_response(&e),
+    }
+}
+
+async fn handle_export(req: HttpRequest, data: web::Data<State>, query: Query) -> HttpResponse {
+    let entity = match data.entity(query.get("entity").map(String::as_str)) {
+        Ok(entity) => entity,
+        Err(e) => return HttpResponse::NotFound().body(e),
+    };
+
+    let params = match bind_params(&req, &entity.handler, &query) {
+        Ok(params) => params,
+        Err(e) => return HttpResponse::BadRequest().body(error_message(&e)),
+    };
+
+    let entities = match entity.handler.export(params, role(&req, &entity.handler)) {
+        Ok(entities) => entities,
+        Err(e) => return HttpResponse::NotFound().body(error_message(&e)),
+    };
+
+    let lines = entities.map(|res| match res {
+        Ok((id, res)) => Ok(web::Bytes::from(export_line(id, res))),
+        Err(e) => {
+            log::error!("export failed: {}", error_message(&e));
+            Err(actix_web::error::ErrorInternalServerError(error_message(&e)))
+        }
+    });
+
+    HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        .streaming(lines)
+}
+
+async fn handle_sets(data: web::Data<State>, query: Query) -> HttpResponse {
+    match data.entity(query.get("entity").map(String::as_str)) {
+        Ok(entity) => HttpResponse::Ok().json(sets_to_json(entity.handler.attr_sets())),
+        Err(e) => HttpResponse::NotFound().body(e),
+    }
+}
+
+async fn handle_set(req: HttpRequest, query: Query, entity: usize) -> HttpResponse {
+    let data = req.app_data::<web::Data<State>>().unwrap();
+    let (id, set) = (req.match_info().query("id"), req.match_info().query("set").to_string());
+    let entity = &data.entities[entity];
+    let eh = &entity.handler;
+
+    let id = match entity.kind.id_rule.canonical(id) {
+        Some(id) => id,
+        None => return HttpResponse::BadRequest().body(format!("invalid {} id {}", entity.kind.name, id)),
+    };
+
+    if !eh.attr_sets().iter().any(|(s, _)| *s == set) {
+        return HttpResponse::NotFound().body(format!("unknown attribute set {}", set));
+    }
+
+    let debug = query.get("debug").is_some_and(|v| v == "true");
+    let lookup = match bind_params(&req, eh, &query) {
+        Ok(params) => Lookup { fields: Fields::set(&set), params, role: role(&req, eh) },
+        Err(e) => return HttpResponse::BadRequest().body(error_message(&e)),
+    };
+
+    if query.get("explain").is_some_and(|v| v == "true") {
+        return HttpResponse::Ok().json(explain_to_json(eh.explain(&id, &lookup)));
+    }
+
+    let resp = match eh.get_entity(&id, &lookup).await {
+        Err(e) => return error_response(&e),
+        Ok(v) => v
+    };
+
+    HttpResponse::Ok().json(set_to_json(resp, &set, debug))
 }
 
-async fn handle(req: HttpRequest) -> HttpResponse {
+async fn handle(req: HttpRequest, entity: usize) -> HttpResponse {
     if let Some(id) = req.match_info().get("id") {
         let data = req.app_data::<web::Data<State>>().unwrap();
-        let mut eh = data.entity_handler.lock().unwrap();
+        let entity = &data.entities[entity];
+        let eh = &entity.handler;
 
-        let resp = match eh.get_entity(id).await {
-            Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
-            Ok(v) => v
+        let id = match entity.kind.id_rule.canonical(id) {
+            Some(id) => id,
+            None => return HttpResponse::BadRequest().body(format!("invalid {} id {}", entity.kind.name, id)),
+        };
+
+        let params = web::Query::<HashMap<String, String>>::from_query(req.query_string())
+            .map(|q| q.into_inner())
+            .unwrap_or_default();
+
+        let debug = params.get("debug").is_some_and(|v| v == "true");
+        let fields = params.get("fields").map(|f| Fields::parse(f)).unwrap_or_default();
+        let lookup = match bind_params(&req, eh, &params) {
+            Ok(params) => Lookup { fields, params, role: role(&req, eh) },
+            Err(e) => return HttpResponse::BadRequest().body(error_message(&e)),
         };
 
-        let mut obj = serde_json::Map::new();
-        for (k, v) in resp {
-            let mut in_obj = serde_json::Map::new();
-            for (k, v) in v {
-                let val = match v {
-                    Value::String(vv) => serde_json::Value::String(vv),
-                    Value::Array(vv) => serde_json::Value::Array(vv.iter().map(|e| {
-                        serde_json::Value::String(e.to_string())
-                    }).collect())
-                };
-                in_obj.insert(k, val);
-            }
-            obj.insert(k, serde_json::Value::Object(in_obj));
+        if params.get("explain").is_some_and(|v| v == "true") {
+            return HttpResponse::Ok().json(explain_to_json(eh.explain(&id, &lookup)));
         }
 
-        return HttpResponse::Ok().json(obj);
+        let resp = match eh.get_entity(&id, &lookup).await {
+            Err(e) => return error_response(&e),
+            Ok(v) => v
+        };
+
+        return HttpResponse::Ok().json(fetched_to_json(resp, debug));
     }
 
     HttpResponse::Ok().finish()
-}
\ No newline at end of file
+}
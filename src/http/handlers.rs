@@ -1,31 +1,291 @@
-use crate::domain::fetcher::{Error, Fetcher, Value};
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+use futures_util::Stream;
+use crate::config::config::{Connection, EntityType, ParamSpec};
+use crate::domain::fetcher::{BackendStatus, Entity, Error, Fetched, Fetcher, GroupPlan, IdResult, Lookup, Meta, Params, Value};
 
 pub struct EntityHandler {
-    fetcher: Fetcher
+    fetcher: Arc<Fetcher>
+}
+
+/// An entity type and the handler serving it.
+pub struct EntityService {
+    pub kind: EntityType,
+    pub handler: EntityHandler,
+}
+
+/// Loads every entity type of the config, in config order.
+pub fn load_entities(config_path: &str) -> Result<Vec<EntityService>, String> {
+    let entities = Fetcher::load(config_path).map_err(|e| match e {
+        Error::ConfigFileErr(msg) => format!("failed to read config file: {}", msg),
+        _ => "failed to init fetcher".to_string()
+    })?;
+
+    Ok(entities.into_iter().map(|(kind, fetcher)| EntityService {
+        kind,
+        handler: EntityHandler { fetcher: Arc::new(fetcher) },
+    }).collect())
+}
+
+/// Finds an entity by name, the first one when no name is given.
+pub fn find_entity<'a>(entities: &'a [EntityService], name: Option<&str>) -> Result<&'a EntityService, String> {
+    match name {
+        None => entities.first().ok_or_else(|| String::from("no entities")),
+        Some(name) => entities.iter().find(|e| e.kind.name == name).ok_or_else(|| format!("unknown entity {}", name)),
+    }
 }
 
 impl EntityHandler {
-    pub fn new(config_path: &str) -> Result<Self, String> {
-        let fetcher = Fetcher::new(config_path).map_err(|e| match e {
-            Error::ConfigFileErr(msg) => format!("failed to read config file: {}", msg),
-            _ => format!("failed to init fetcher")
-        })?;
-
-        Ok(Self {
-            fetcher
-        })
-    }
-
-    pub async fn get_entity(&self, id: &str) -> Result<Vec<(String, Vec<(String, Value)>)>, String>{
-        let resp = match self.fetcher.fetch_id(id).await {
-            Ok(v) => v,
-            Err(e) => return match e {
-                Error::ConfigFileErr(msg) => Err(msg),
-                Error::ExecErr(msg) => Err(msg),
-                Error::InvalidConfig => Err(String::from("invalid config"))
-            }
+    pub fn requires(&self, conn: &Connection) -> bool {
+        self.fetcher.requires(conn)
+    }
+
+    pub fn attr_sets(&self) -> Vec<(String, Vec<String>)> {
+        self.fetcher.attr_sets()
+    }
+
+    pub fn explain(&self, id: &str, lookup: &Lookup) -> Vec<GroupPlan> {
+        self.fetcher.explain(id, lookup)
+    }
+
+    pub async fn readiness(&self, timeout: Duration) -> Vec<BackendStatus> {
+        self.fetcher.readiness(timeout).await
+    }
+
+    pub fn evict(&self, id: &str) -> usize {
+        self.fetcher.evict(id)
+    }
+
+    pub fn flush_cache(&self) -> usize {
+        self.fetcher.flush_cache()
+    }
+
+    pub async fn get_entity(&self, id: &str, lookup: &Lookup) -> Result<Fetched, Error>{
+        self.fetcher.fetch_id(id, lookup).await
+    }
+
+    pub async fn get_entities(&self, ids: &[String], lookup: &Lookup) -> Result<Vec<IdResult>, Error> {
+        self.fetcher.fetch_ids(ids, lookup).await
+    }
+
+    pub fn export(&self, params: Params, role: Option<String>) -> Result<impl Stream<Item = Result<IdResult, Error>> + 'static, Error> {
+        self.fetcher.clone().export(params, role)
+    }
+
+    pub fn max_batch_ids(&self) -> usize {
+        self.fetcher.max_batch_ids()
+    }
+
+    pub fn admits(&self, credential: &str) -> bool {
+        self.fetcher.admits(credential)
+    }
+
+    pub fn role_header(&self) -> Option<&str> {
+        self.fetcher.role_header()
+    }
+
+    pub fn role(&self, credential: &str) -> Option<String> {
+        self.fetcher.role(credential)
+    }
+
+    pub fn bind(&self, input: impl Fn(&ParamSpec) -> Option<String>) -> Result<Params, Error> {
+        self.fetcher.bind(input)
+    }
+}
+
+pub fn error_message(e: &Error) -> String {
+    match e {
+        Error::ConfigFileErr(msg) => msg.to_string(),
+        Error::ExecErr(msg) => msg.to_string(),
+        Error::InvalidConfig => String::from("invalid config"),
+        Error::Timeout(groups) => format!("timed out: {}", group_names(groups).join(", ")),
+        Error::UnknownFields(fields) => format!("unknown fields: {}", fields.join(", ")),
+        Error::InvalidParam(msg) => msg.to_string(),
+        Error::Conflict(attr) => format!("conflicting values for {}", attr),
+    }
+}
+
+fn group_names(groups: &[(String, usize)]) -> Vec<String> {
+    groups.iter().map(|(attr, i)| format!("{}[{}]", attr, i)).collect()
+}
+
+pub fn entity_to_json(entity: Entity) -> serde_json::Value {
+    let mut obj = serde_json::Map::new();
+    for (k, v) in entity {
+        obj.insert(k, values_to_json(v));
+    }
+
+    serde_json::Value::Object(obj)
+}
+
+fn values_to_json(values: Vec<(String, Value)>) -> serde_json::Value {
+    let mut obj = serde_json::Map::new();
+    for (k, v) in values {
+        let val = match v {
+            Value::String(vv) => serde_json::Value::String(vv),
+            Value::Array(vv) => serde_json::Value::Array(vv.iter().map(|e| {
+                serde_json::Value::String(e.to_string())
+            }).collect()),
+            Value::Rows(rows) => serde_json::Value::Array(rows.into_iter().map(values_to_json).collect()),
+            Value::Object(values) => values_to_json(values),
+        };
+        obj.insert(k, val);
+    }
+
+    serde_json::Value::Object(obj)
+}
+
+/// Serialises a fetched entity. `_meta` is only added in debug mode, `_timed_out`
+/// and `_unavailable` whenever some groups were left out.
+pub fn fetched_to_json(fetched: Fetched, debug: bool) -> serde_json::Value {
+    let mut obj = entity_to_json(fetched.entity);
+
+    if debug {
+        add_meta(&mut obj, fetched.meta);
+    }
+
+    if !fetched.timed_out.is_empty() {
+        obj["_timed_out"] = json!(group_names(&fetched.timed_out));
+    }
+
+    if !fetched.unavailable.is_empty() {
+        obj["_unavailable"] = json!(group_names(&fetched.unavailable));
+    }
+
+    obj
+}
+
+/// Serialises a single attribute set of a fetched entity, with `_timed_out`
+/// and `_unavailable` moved inside it.
+pub fn set_to_json(fetched: Fetched, set: &str, debug: bool) -> serde_json::Value {
+    let mut obj = fetched_to_json(fetched, debug);
+    let mut out = obj.get_mut(set).map(serde_json::Value::take).unwrap_or(json!({}));
+
+    for k in ["_timed_out", "_unavailable"] {
+        if let Some(v) = obj.get_mut(k) {
+            out[k] = v.take();
+        }
+    }
+
+    out
+}
+
+pub fn sets_to_json(sets: Vec<(String, Vec<String>)>) -> serde_json::Value {
+    let mut obj = serde_json::Map::new();
+    for (set, attrs) in sets {
+        obj.insert(set, json!(attrs));
+    }
+
+    serde_json::Value::Object(obj)
+}
+
+/// Serialises a batch as a map from id to entity. Ids that failed map to
+/// an object holding only `_error`.
+pub fn batch_to_json(results: Vec<IdResult>, debug: bool) -> serde_json::Value {
+    let mut obj = serde_json::Map::new();
+    for (id, res) in results {
+        let val = match res {
+            Ok(fetched) => fetched_to_json(fetched, debug),
+            Err(e) => json!({"_error": error_message(&e)}),
         };
+        obj.insert(id, val);
+    }
+
+    serde_json::Value::Object(obj)
+}
+
+/// Serialises one exported entity as a single NDJSON line.
+pub fn export_line(id: String, res: Result<Fetched, Error>) -> String {
+    let obj = match res {
+        Ok(fetched) => json!({"id": id, "entity": fetched_to_json(fetched, false)}),
+        Err(e) => json!({"id": id, "error": error_message(&e)}),
+    };
+
+    format!("{}\n", obj)
+}
+
+fn add_meta(obj: &mut serde_json::Value, meta: Meta) {
+    for (k, v) in meta {
+        let mut in_meta = serde_json::Map::new();
+        for (k, l) in v {
+            let mut lineage = json!({
+                "group": l.group,
+                "rows": l.rows,
+                "latency_ms": l.latency.as_secs_f64() * 1000.0,
+                "cached": l.cached,
+                "column": l.column,
+            });
+            match &l.sources[..] {
+                [(conn, query)] => {
+                    lineage["connection"] = json!(conn.name());
+                    lineage["query"] = json!(query);
+                }
+                sources => {
+                    lineage["sources"] = sources.iter().map(|(conn, query)| json!({"connection": conn.name(), "query": query})).collect();
+                }
+            }
+            in_meta.insert(k, lineage);
+        }
 
-        Ok(resp)
+        if let Some(serde_json::Value::Object(in_obj)) = obj.get_mut(&k) {
+            in_obj.insert(String::from("_meta"), serde_json::Value::Object(in_meta));
+        }
     }
 }
+
+pub fn explain_to_json(plans: Vec<GroupPlan>) -> serde_json::Value {
+    let groups = plans.into_iter().map(|p| {
+        let mut params = serde_json::Map::new();
+        for (k, v) in p.params {
+            params.insert(k, serde_json::Value::String(v));
+        }
+
+        let mut mapping = serde_json::Map::new();
+        for (k, v) in p.mapping {
+            mapping.insert(k, serde_json::Value::String(v));
+        }
+
+        let mut plan = json!({
+            "attribute": p.attr,
+            "group": p.group,
+            "params": params,
+            "expected_rows": p.exp_rows,
+            "select_attributes": mapping,
+            "children": explain_to_json(p.children),
+        });
+        match (p.conn, p.query) {
+            (Some(conn), Some(query)) => {
+                plan["connection"] = json!(conn.name());
+                plan["query"] = json!(query);
+            }
+            _ => plan["sources"] = explain_to_json(p.sources),
+        }
+        plan
+    }).collect();
+
+    serde_json::Value::Array(groups)
+}
+
+pub fn readiness_to_json(statuses: &[BackendStatus]) -> serde_json::Value {
+    let mut conns = serde_json::Map::new();
+    for s in statuses {
+        let mut obj = json!({
+            "status": if s.status.is_ok() { "up" } else { "down" },
+            "required": s.required,
+        });
+        if let Err(e) = &s.status {
+            obj["error"] = serde_json::Value::String(e.to_string());
+        }
+        if let Some(circuit) = s.circuit {
+            obj["circuit"] = serde_json::Value::String(circuit.to_string());
+        }
+        conns.insert(s.conn.name().to_string(), obj);
+    }
+
+    let ready = statuses.iter().all(|s| !s.required || s.status.is_ok());
+    json!({
+        "status": if ready { "ready" } else { "not ready" },
+        "connections": conns,
+    })
+}
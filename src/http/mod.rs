@@ -1,3 +1,3 @@
 pub mod server;
 mod factory;
-mod handlers;
\ No newline at end of file
+pub mod handlers;
\ No newline at end of file
@@ -1,27 +1,60 @@
-use std::error::Error;
 use std::io;
-use std::os::macos::raw::stat;
-use std::sync::{Arc, Mutex};
-use actix_web::{App, HttpServer, web};
-use mysql_async::prelude::Query;
+use std::time::Instant;
+use actix_web::{App, HttpServer, middleware, web};
+use actix_web::dev::Service;
 use crate::http::factory::route_factory;
-use crate::http::handlers::EntityHandler;
+use crate::http::handlers::{find_entity, load_entities, EntityService};
+use crate::metrics::metrics::{metrics, HTTP_DURATION, HTTP_REQUESTS};
 
 pub struct State {
-    pub entity_handler: Mutex<EntityHandler>
+    pub entities: Vec<EntityService>
 }
 
-pub async fn run_server(addr: &str, config_path: &str) -> Result<(), io::Error> {
-    let eh = EntityHandler::new(config_path).unwrap();
+impl State {
+    pub fn entity(&self, name: Option<&str>) -> Result<&EntityService, String> {
+        find_entity(&self.entities, name)
+    }
+}
+
+pub struct ServerConfig {
+    pub addr: String,
+    pub config_path: String,
+    pub workers: Option<usize>,
+    pub shutdown_timeout: u64,
+}
+
+pub async fn run_server(cfg: ServerConfig) -> Result<(), io::Error> {
+    let entities = load_entities(&cfg.config_path).map_err(io::Error::other)?;
+    let routes = entities.iter().map(|e| e.kind.route.clone()).collect::<Vec<_>>();
 
     let state = web::Data::new(State{
-        entity_handler: Mutex::new(eh),
+        entities,
     });
 
-    HttpServer::new(move || {
-        let app = App::new().
+    let mut server = HttpServer::new(move || {
+        App::new().
+            wrap(middleware::Logger::default()).
+            wrap_fn(|req, srv| {
+                let start = Instant::now();
+                let route = req.match_pattern().unwrap_or(String::from("unmatched"));
+                let fut = srv.call(req);
+                async move {
+                    let res = fut.await?;
+                    let status = res.status().as_u16().to_string();
+                    let labels = [("route", route.as_str()), ("status", status.as_str())];
+                    metrics().inc(HTTP_REQUESTS, &labels, 1);
+                    metrics().observe(HTTP_DURATION, &labels, start.elapsed());
+                    Ok(res)
+                }
+            }).
             app_data(state.clone()).
-            configure(route_factory);
-        app
-    }).bind(addr)?.run().await
-}
\ No newline at end of file
+            configure(|c| route_factory(c, &routes))
+    }).shutdown_timeout(cfg.shutdown_timeout);
+
+    if let Some(workers) = cfg.workers {
+        server = server.workers(workers);
+    }
+
+    log::info!("listening on {}", cfg.addr);
+    server.bind(cfg.addr)?.run().await
+}
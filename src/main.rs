@@ -1,15 +1,241 @@
-use crate::http::server::run_server;
-use crate::storage::connection::Connection;
-use crate::storage::db::postgres::Client;
-use crate::storage::db::mysql;
+use std::io::{self, Write};
+use std::process;
+use futures_util::StreamExt;
+use clap::{Parser, Subcommand};
+use crate::config::config::ParamSpec;
+use crate::domain::fetcher::{Fetcher, Fields, Lookup, Params};
+use crate::http::handlers::{error_message, explain_to_json, export_line, fetched_to_json, find_entity, load_entities, EntityService};
+use crate::http::server::{run_server, ServerConfig};
+use crate::storage::storage::Storage;
 
 mod config;
 mod storage;
 mod http;
 mod domain;
+mod metrics;
+
+#[derive(Parser)]
+#[command(about = "Fetches entities from the configured databases")]
+struct Cli {
+    /// Path to the attribute groups config
+    #[arg(long, env = "FETCHER_CONFIG", default_value = "./config.json", global = true)]
+    config: String,
+
+    /// Address the HTTP server binds to
+    #[arg(long, env = "FETCHER_BIND", default_value = "127.0.0.1:8099", global = true)]
+    bind: String,
+
+    /// Number of HTTP workers, defaults to the number of CPUs
+    #[arg(long, env = "FETCHER_WORKERS", global = true)]
+    workers: Option<usize>,
+
+    /// Log filter, e.g. `info` or `parser=debug`
+    #[arg(long, env = "FETCHER_LOG_LEVEL", default_value = "info", global = true)]
+    log_level: String,
+
+    /// Seconds to wait for in-flight requests on shutdown
+    #[arg(long, env = "FETCHER_SHUTDOWN_TIMEOUT", default_value_t = 30, global = true)]
+    shutdown_timeout: u64,
+
+    /// Entity type to fetch, export or explain, defaults to the first one
+    #[arg(long, global = true)]
+    entity: Option<String>,
+
+    /// Value of a named query param, `name=value`; may be repeated
+    #[arg(long = "param", global = true)]
+    params: Vec<String>,
+
+    /// Role to fetch as, decides which attributes are returned unmasked
+    #[arg(long, global = true)]
+    role: Option<String>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the HTTP server (default)
+    Serve,
+    /// Parse the config and report errors
+    Validate,
+    /// Fetch one entity and print it as JSON
+    Fetch {
+        id: String,
+        /// Comma separated attributes to fetch, e.g. `attributes.firstname,names`
+        #[arg(long)]
+        fields: Option<String>,
+    },
+    /// Print the rendered queries for an id without running them
+    Explain { id: String },
+    /// Stream every entity of the configured export source as NDJSON
+    Export,
+}
 
 #[tokio::main]
 async fn main() {
-    let config_path = "./config.json";
-    run_server("127.0.0.1:8099", config_path).await.unwrap();
-}
\ No newline at end of file
+    let cli = Cli::parse();
+
+    env_logger::Builder::new().parse_filters(&cli.log_level).init();
+
+    let res = match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => serve(cli.config, cli.bind, cli.workers, cli.shutdown_timeout).await,
+        Command::Validate => validate(&cli.config),
+        Command::Fetch { id, fields } => fetch(&cli.config, cli.entity.as_deref(), &cli.params, cli.role, &id, fields.as_deref()).await,
+        Command::Explain { id } => explain(&cli.config, cli.entity.as_deref(), &cli.params, &id),
+        Command::Export => export(&cli.config, cli.entity.as_deref(), &cli.params, cli.role).await,
+    };
+
+    if let Err(e) = res {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+async fn serve(config_path: String, addr: String, workers: Option<usize>, shutdown_timeout: u64) -> Result<(), String> {
+    run_server(ServerConfig { addr, config_path, workers, shutdown_timeout }).await
+        .map_err(|e| e.to_string())
+}
+
+fn validate(config_path: &str) -> Result<(), String> {
+    let entities = config::config::load_entities(config_path).map_err(|e| format!("invalid config: {}", e))?;
+    for (entity, cfg) in entities.iter() {
+        let groups: usize = cfg.attr_groups.iter().map(|(_, g)| g.len()).sum();
+        println!("{} ({}): {} attribute sets, {} groups", entity.name, entity.route, cfg.attr_groups.len(), groups);
+    }
+    println!("config is valid");
+    Ok(())
+}
+
+/// Reads params from `name=value` pairs by their name, whatever part of a request they are read from.
+fn param_input(params: &[String]) -> Result<impl Fn(&ParamSpec) -> Option<String> + '_, String> {
+    let values = params.iter()
+        .map(|p| p.split_once('=').ok_or_else(|| format!("param {} is not name=value", p)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(move |spec: &ParamSpec| values.iter().find(|(k, _)| *k == spec.name).map(|(_, v)| v.to_string()))
+}
+
+/// Binds the entity's params from `name=value` pairs.
+fn bind_params(entity: &EntityService, params: &[String]) -> Result<Params, String> {
+    entity.handler.bind(param_input(params)?).map_err(|e| error_message(&e))
+}
+
+async fn fetch(config_path: &str, entity: Option<&str>, params: &[String], role: Option<String>, id: &str, fields: Option<&str>) -> Result<(), String> {
+    let entities = load_entities(config_path)?;
+    let entity = find_entity(&entities, entity)?;
+    let id = entity.kind.id_rule.canonical(id).ok_or_else(|| format!("invalid {} id {}", entity.kind.name, id))?;
+
+    let fields = fields.map(Fields::parse).unwrap_or_default();
+    let params = bind_params(entity, params)?;
+    let entity = entity.handler.get_entity(&id, &Lookup { fields, params, role }).await.map_err(|e| error_message(&e))?;
+    let out = serde_json::to_string_pretty(&fetched_to_json(entity, false)).map_err(|e| e.to_string())?;
+    println!("{}", out);
+    Ok(())
+}
+
+fn explain(config_path: &str, entity: Option<&str>, params: &[String], id: &str) -> Result<(), String> {
+    let entities = config::config::load_entities(config_path).map_err(|e| format!("invalid config: {}", e))?;
+    let (kind, cfg) = match entity {
+        None => entities.into_iter().next(),
+        Some(name) => entities.into_iter().find(|(e, _)| e.name == name),
+    }.ok_or_else(|| format!("unknown entity {}", entity.unwrap_or_default()))?;
+    let id = kind.id_rule.canonical(id).ok_or_else(|| format!("invalid {} id {}", kind.name, id))?;
+    let fetcher = Fetcher::from_config(cfg, Storage::new());
+    let params = fetcher.bind(param_input(params)?).map_err(|e| error_message(&e))?;
+    let out = serde_json::to_string_pretty(&explain_to_json(fetcher.explain(&id, &Lookup { params, ..Lookup::default() }))).map_err(|e| e.to_string())?;
+    println!("{}", out);
+    Ok(())
+}
+
+async fn export(config_path: &str, entity: Option<&str>, params: &[String], role: Option<String>) -> Result<(), String> {
+    let entities = load_entities(config_path)?;
+    let entity = find_entity(&entities, entity)?;
+    let params = bind_params(entity, params)?;
+    let mut entities = std::pin::pin!(entity.handler.export(params, role).map_err(|e| error_message(&e))?);
+
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    while let Some(res) = entities.next().await {
+        let (id, res) = res.map_err(|e| error_message(&e))?;
+        out.write_all(export_line(id, res).as_bytes()).map_err(|e| e.to_string())?;
+    }
+
+    out.flush().map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use clap::Parser;
+    use crate::config::config::{ParamSource, ParamSpec, ParamType};
+    use super::{Cli, Command};
+
+    #[test]
+    fn parse_args() {
+        let cli = Cli::try_parse_from(["fetcher"]).unwrap();
+        assert!(cli.command.is_none());
+        assert_eq!(cli.config, "./config.json");
+        assert_eq!(cli.bind, "127.0.0.1:8099");
+        assert_eq!(cli.shutdown_timeout, 30);
+
+        let cli = Cli::try_parse_from(["fetcher", "serve", "--bind", "0.0.0.0:80", "--workers", "2"]).unwrap();
+        assert!(matches!(cli.command, Some(Command::Serve)));
+        assert_eq!(cli.bind, "0.0.0.0:80");
+        assert_eq!(cli.workers, Some(2));
+
+        let cli = Cli::try_parse_from(["fetcher", "--config", "c.json", "validate"]).unwrap();
+        assert!(matches!(cli.command, Some(Command::Validate)));
+        assert_eq!(cli.config, "c.json");
+
+        let cli = Cli::try_parse_from([
+            "fetcher", "fetch", "42", "--fields", "attributes.fn,names",
+            "--entity", "users", "--param", "tenant=1", "--param", "as_of=2024-01-01", "--role", "admin",
+        ]).unwrap();
+        match cli.command {
+            Some(Command::Fetch { id, fields }) => {
+                assert_eq!(id, "42");
+                assert_eq!(fields.as_deref(), Some("attributes.fn,names"));
+            },
+            _ => panic!("expected fetch"),
+        }
+        assert_eq!(cli.entity.as_deref(), Some("users"));
+        assert_eq!(cli.params, vec!["tenant=1", "as_of=2024-01-01"]);
+        assert_eq!(cli.role.as_deref(), Some("admin"));
+
+        let cli = Cli::try_parse_from(["fetcher", "explain", "7"]).unwrap();
+        assert!(matches!(cli.command, Some(Command::Explain { id }) if id == "7"));
+
+        let cli = Cli::try_parse_from(["fetcher", "export", "--param", "tenant=1"]).unwrap();
+        assert!(matches!(cli.command, Some(Command::Export)));
+        assert_eq!(cli.params, vec!["tenant=1"]);
+
+        assert!(Cli::try_parse_from(["fetcher", "fetch"]).is_err());
+        assert!(Cli::try_parse_from(["fetcher", "explain"]).is_err());
+        assert!(Cli::try_parse_from(["fetcher", "purge"]).is_err());
+        assert!(Cli::try_parse_from(["fetcher", "--workers", "many"]).is_err());
+    }
+
+    #[test]
+    fn param_input() {
+        let spec = |name: &str, source: ParamSource| ParamSpec { name: name.to_string(), source, ty: ParamType::String, default: None };
+        let params = vec!["tenant=1".to_string(), "q=a=b".to_string(), "region=eu".to_string()];
+        let input = super::param_input(&params).unwrap();
+        assert_eq!(input(&spec("tenant", ParamSource::Path("tenant".into()))), Some("1".into()));
+        assert_eq!(input(&spec("q", ParamSource::Query("q".into()))), Some("a=b".into()));
+        assert_eq!(input(&spec("missing", ParamSource::Header("missing".into()))), None);
+        // matched on the param's name, not on where a request carries it
+        assert_eq!(input(&spec("region", ParamSource::Header("X-Region".into()))), Some("eu".into()));
+        assert_eq!(input(&spec("zone", ParamSource::Query("region".into()))), None);
+
+        assert!(super::param_input(&["tenant".to_string()]).is_err());
+    }
+
+    #[test]
+    fn subcommands() {
+        assert!(super::validate("./config.json").is_ok());
+        assert!(super::validate("./missing.json").is_err());
+
+        assert!(super::explain("./config.json", None, &[], "42").is_ok());
+        assert!(super::explain("./config.json", Some("unknown"), &[], "42").is_err());
+        assert!(super::explain("./config.json", None, &["tenant".to_string()], "42").is_err());
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+
+pub const HTTP_REQUESTS: &str = "fetcher_http_requests_total";
+pub const HTTP_DURATION: &str = "fetcher_http_request_duration_seconds";
+pub const QUERY_DURATION: &str = "fetcher_query_duration_seconds";
+pub const QUERY_ERRORS: &str = "fetcher_query_errors_total";
+pub const QUERY_RETRIES: &str = "fetcher_query_retries_total";
+pub const POOL_IN_USE: &str = "fetcher_pool_connections_in_use";
+pub const POOL_MAX: &str = "fetcher_pool_connections_max";
+pub const GROUP_ROWS: &str = "fetcher_group_rows_total";
+pub const CACHE_HITS: &str = "fetcher_cache_hits_total";
+pub const CACHE_MISSES: &str = "fetcher_cache_misses_total";
+
+const BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+fn help(name: &str) -> &'static str {
+    match name {
+        HTTP_REQUESTS => "HTTP requests by route and status",
+        HTTP_DURATION => "HTTP request latency by route and status",
+        QUERY_DURATION => "Query latency by connection",
+        QUERY_ERRORS => "Failed queries by connection",
+        QUERY_RETRIES => "Retried queries by connection and error kind",
+        POOL_IN_USE => "Connections currently executing a query",
+        POOL_MAX => "Maximum number of connections in the pool",
+        GROUP_ROWS => "Rows returned by attribute group",
+        CACHE_HITS => "Query results served from the cache by connection",
+        CACHE_MISSES => "Cacheable queries sent to the backend by connection",
+        _ => "",
+    }
+}
+
+struct Histogram {
+    buckets: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+#[derive(Default)]
+struct Registry {
+    counters: BTreeMap<&'static str, BTreeMap<String, u64>>,
+    gauges: BTreeMap<&'static str, BTreeMap<String, i64>>,
+    histograms: BTreeMap<&'static str, BTreeMap<String, Histogram>>,
+}
+
+/// In-process metrics rendered in the Prometheus text format.
+pub struct Metrics {
+    registry: Mutex<Registry>
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self {
+            registry: Mutex::new(Registry::default()),
+        }
+    }
+
+    pub fn inc(&self, name: &'static str, labels: &[(&str, &str)], v: u64) {
+        let mut reg = self.registry.lock().unwrap();
+        *reg.counters.entry(name).or_default().entry(render_labels(labels)).or_default() += v;
+    }
+
+    pub fn set(&self, name: &'static str, labels: &[(&str, &str)], v: i64) {
+        let mut reg = self.registry.lock().unwrap();
+        reg.gauges.entry(name).or_default().insert(render_labels(labels), v);
+    }
+
+    pub fn add(&self, name: &'static str, labels: &[(&str, &str)], v: i64) {
+        let mut reg = self.registry.lock().unwrap();
+        *reg.gauges.entry(name).or_default().entry(render_labels(labels)).or_default() += v;
+    }
+
+    pub fn observe(&self, name: &'static str, labels: &[(&str, &str)], d: Duration) {
+        let secs = d.as_secs_f64();
+        let mut reg = self.registry.lock().unwrap();
+        let h = reg.histograms.entry(name).or_default().entry(render_labels(labels)).or_insert(Histogram {
+            buckets: [0; BUCKETS.len()],
+            sum: 0.0,
+            count: 0,
+        });
+
+        for (i, le) in BUCKETS.iter().enumerate() {
+            if secs <= *le {
+                h.buckets[i] += 1;
+            }
+        }
+        h.sum += secs;
+        h.count += 1;
+    }
+
+    pub fn render(&self) -> String {
+        let reg = self.registry.lock().unwrap();
+        let mut out = String::new();
+
+        for (name, series) in reg.counters.iter() {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} counter", name, help(name), name);
+            for (labels, v) in series {
+                let _ = writeln!(out, "{}{} {}", name, labels, v);
+            }
+        }
+
+        for (name, series) in reg.gauges.iter() {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} gauge", name, help(name), name);
+            for (labels, v) in series {
+                let _ = writeln!(out, "{}{} {}", name, labels, v);
+            }
+        }
+
+        for (name, series) in reg.histograms.iter() {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} histogram", name, help(name), name);
+            for (labels, h) in series {
+                for (i, le) in BUCKETS.iter().enumerate() {
+                    let _ = writeln!(out, "{}_bucket{} {}", name, with_le(labels, &le.to_string()), h.buckets[i]);
+                }
+                let _ = writeln!(out, "{}_bucket{} {}", name, with_le(labels, "+Inf"), h.count);
+                let _ = writeln!(out, "{}_sum{} {}", name, labels, h.sum);
+                let _ = writeln!(out, "{}_count{} {}", name, labels, h.count);
+            }
+        }
+
+        out
+    }
+}
+
+fn render_labels(labels: &[(&str, &str)]) -> String {
+    if labels.is_empty() {
+        return String::default();
+    }
+
+    let pairs = labels.iter().map(|(k, v)| {
+        let v = v.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+        format!("{}=\"{}\"", k, v)
+    }).collect::<Vec<_>>();
+
+    format!("{{{}}}", pairs.join(","))
+}
+
+fn with_le(labels: &str, le: &str) -> String {
+    match labels.strip_suffix('}') {
+        Some(l) => format!("{},le=\"{}\"}}", l, le),
+        None => format!("{{le=\"{}\"}}", le),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+    use crate::metrics::metrics::{Metrics, GROUP_ROWS, QUERY_DURATION};
+
+    #[test]
+    fn render() {
+        let m = Metrics::new();
+        m.inc(GROUP_ROWS, &[("attribute", "names"), ("group", "0")], 2);
+        m.inc(GROUP_ROWS, &[("attribute", "names"), ("group", "0")], 3);
+        m.observe(QUERY_DURATION, &[("connection", "mysql")], Duration::from_millis(20));
+
+        let out = m.render();
+        assert!(out.contains("# TYPE fetcher_group_rows_total counter\n"));
+        assert!(out.contains("fetcher_group_rows_total{attribute=\"names\",group=\"0\"} 5\n"));
+        assert!(out.contains("fetcher_query_duration_seconds_bucket{connection=\"mysql\",le=\"0.01\"} 0\n"));
+        assert!(out.contains("fetcher_query_duration_seconds_bucket{connection=\"mysql\",le=\"0.025\"} 1\n"));
+        assert!(out.contains("fetcher_query_duration_seconds_bucket{connection=\"mysql\",le=\"+Inf\"} 1\n"));
+        assert!(out.contains("fetcher_query_duration_seconds_count{connection=\"mysql\"} 1\n"));
+    }
+}
@@ -0,0 +1,138 @@
+use std::error::Error;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use crate::config::config::BreakerSettings;
+
+/// Returned by `Storage::exec` while the circuit of a connection is open.
+#[derive(Debug)]
+pub struct CircuitOpen;
+
+impl fmt::Display for CircuitOpen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "circuit is open")
+    }
+}
+
+impl Error for CircuitOpen {}
+
+enum State {
+    Closed { failures: u32 },
+    Open { until: Instant },
+    HalfOpen { in_flight: u32, successes: u32 },
+}
+
+pub struct CircuitBreaker {
+    settings: BreakerSettings,
+    state: Mutex<State>,
+}
+
+/// Permission to run one query. Dropping it without calling `success` or
+/// `failure`, e.g. when the query times out, counts as a failure.
+pub struct Permit<'a> {
+    breaker: &'a CircuitBreaker,
+    done: bool,
+}
+
+impl Permit<'_> {
+    pub fn success(mut self) {
+        self.done = true;
+        self.breaker.record(true);
+    }
+
+    pub fn failure(mut self) {
+        self.done = true;
+        self.breaker.record(false);
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        if !self.done {
+            self.breaker.record(false);
+        }
+    }
+}
+
+impl CircuitBreaker {
+    pub fn new(settings: BreakerSettings) -> Self {
+        Self {
+            settings,
+            state: Mutex::new(State::Closed { failures: 0 }),
+        }
+    }
+
+    pub fn acquire(&self) -> Result<Permit<'_>, CircuitOpen> {
+        let mut state = self.state.lock().unwrap();
+
+        if let State::Open { until } = *state {
+            if Instant::now() < until {
+                return Err(CircuitOpen)
+            }
+            *state = State::HalfOpen { in_flight: 0, successes: 0 };
+        }
+
+        if let State::HalfOpen { in_flight, .. } = &mut *state {
+            if *in_flight >= self.settings.half_open_probes {
+                return Err(CircuitOpen)
+            }
+            *in_flight += 1;
+        }
+
+        Ok(Permit { breaker: self, done: false })
+    }
+
+    fn record(&self, ok: bool) {
+        let mut state = self.state.lock().unwrap();
+        let open = State::Open { until: Instant::now() + Duration::from_millis(self.settings.open_ms) };
+
+        *state = match *state {
+            State::Closed { .. } if ok => State::Closed { failures: 0 },
+            State::Closed { failures } if failures + 1 >= self.settings.failure_threshold => open,
+            State::Closed { failures } => State::Closed { failures: failures + 1 },
+            State::HalfOpen { successes, .. } if ok && successes + 1 >= self.settings.half_open_probes => State::Closed { failures: 0 },
+            State::HalfOpen { in_flight, successes } if ok => State::HalfOpen { in_flight: in_flight - 1, successes: successes + 1 },
+            State::HalfOpen { .. } => open,
+            // a query that was let through before the circuit opened
+            State::Open { until } => State::Open { until },
+        };
+    }
+
+    pub fn state_name(&self) -> &'static str {
+        match *self.state.lock().unwrap() {
+            State::Closed { .. } => "closed",
+            State::Open { until } if Instant::now() >= until => "half_open",
+            State::Open { .. } => "open",
+            State::HalfOpen { .. } => "half_open",
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread::sleep;
+    use std::time::Duration;
+    use crate::config::config::BreakerSettings;
+    use crate::storage::breaker::CircuitBreaker;
+
+    #[test]
+    fn trip_and_recover() {
+        let breaker = CircuitBreaker::new(BreakerSettings { failure_threshold: 2, open_ms: 20, half_open_probes: 1 });
+
+        breaker.acquire().unwrap().failure();
+        assert_eq!(breaker.state_name(), "closed");
+        drop(breaker.acquire().unwrap());
+        assert_eq!(breaker.state_name(), "open");
+        assert!(breaker.acquire().is_err());
+
+        sleep(Duration::from_millis(30));
+        let probe = breaker.acquire().unwrap();
+        assert!(breaker.acquire().is_err(), "only one probe is let through");
+        probe.failure();
+        assert_eq!(breaker.state_name(), "open");
+
+        sleep(Duration::from_millis(30));
+        breaker.acquire().unwrap().success();
+        assert_eq!(breaker.state_name(), "closed");
+    }
+}
@@ -1,13 +1,197 @@
 use std::error::Error;
 use std::future::Future;
 use std::pin::Pin;
+use crate::config::config::ErrorKind;
 
 pub type ExecResult<'a> = Pin<Box<dyn Future<Output=Result<Vec<Row>, Box<dyn Error>>> + 'a>>;
+pub type PingResult<'a> = Pin<Box<dyn Future<Output=Result<(), Box<dyn Error>>> + 'a>>;
 
-pub trait Connection: Send {
-    fn exec(&self, query: String) -> ExecResult;
+/// A value bound to a named placeholder such as `:tenant`.
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub enum Param {
+    String(String),
+    Integer(i64),
+    /// Kept as validated text so params stay hashable.
+    Number(String),
+    Boolean(bool),
 }
 
+impl Param {
+    pub fn to_text(&self) -> String {
+        match self {
+            Param::String(v) | Param::Number(v) => v.to_string(),
+            Param::Integer(v) => v.to_string(),
+            Param::Boolean(v) => v.to_string(),
+        }
+    }
+}
+
+pub trait Connection: Send + Sync {
+    /// Runs `query` with every `:name` placeholder that has a value in `params` bound to it.
+    fn exec(&self, query: String, params: Vec<(String, Param)>) -> ExecResult<'_>;
+
+    /// Cheap round trip to the backend used by the readiness check.
+    fn ping(&self) -> PingResult<'_>;
+
+    /// Classifies an error returned by `exec` for the retry policy.
+    fn error_kind(&self, _e: &(dyn Error + 'static)) -> ErrorKind {
+        ErrorKind::Other
+    }
+
+    /// Maximum number of queries the connection can run at the same time.
+    fn pool_size(&self) -> usize {
+        1
+    }
+}
+
+/// Runs a cancel future on the current runtime when dropped before `disarm`,
+/// so that a driver can stop the query on the backend when the future running
+/// it is dropped, e.g. on a timeout, instead of leaving it running there.
+pub struct CancelOnDrop(Option<Pin<Box<dyn Future<Output = ()> + Send>>>);
+
+impl CancelOnDrop {
+    pub fn new(cancel: impl Future<Output = ()> + Send + 'static) -> Self {
+        Self(Some(Box::pin(cancel)))
+    }
+
+    /// The query finished, nothing to cancel.
+    pub fn disarm(mut self) {
+        self.0 = None;
+    }
+}
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        if let (Some(cancel), Ok(rt)) = (self.0.take(), tokio::runtime::Handle::try_current()) {
+            rt.spawn(cancel);
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Row {
     pub columns: Vec<(String, String)>
-}
\ No newline at end of file
+}
+/// Replaces the `:name` placeholders of `query` that have a value in `params`
+/// with the marker the driver expects for the n-th parameter (1-based) and
+/// returns the values in order. Quoted text, `::` casts and unknown names are
+/// left alone.
+pub fn positional(query: &str, params: &[(String, Param)], marker: impl Fn(usize) -> String) -> (String, Vec<Param>) {
+    let mut values = vec![];
+    let out = replace_placeholders(query, |name| {
+        let (_, v) = params.iter().find(|(k, _)| k == name)?;
+        values.push(v.clone());
+        Some(marker(values.len()))
+    });
+
+    (out, values)
+}
+
+/// Names of the `:name` placeholders of `query`, in order of first use.
+pub fn placeholders(query: &str) -> Vec<String> {
+    let mut names: Vec<String> = vec![];
+    replace_placeholders(query, |name| {
+        if !names.iter().any(|n| n == name) {
+            names.push(name.to_string());
+        }
+        None
+    });
+
+    names
+}
+
+/// Rewrites `'__PID__'` and a bare `__PID__` of `query` into the `:__PID__`
+/// placeholder, so the id is bound rather than pasted into the query. Returns
+/// `None` when `__PID__` is part of other quoted text, such as `'%__PID__%'`.
+pub fn bind_id(query: &str) -> Option<String> {
+    let query = query.replace("'__PID__'", "__PID__");
+    let mut out = String::with_capacity(query.len());
+    let mut quote = None;
+    let mut rest = query.as_str();
+
+    while let Some(c) = rest.chars().next() {
+        if rest.starts_with("__PID__") {
+            if quote.is_some() {
+                return None
+            }
+            out.push_str(":__PID__");
+            rest = &rest["__PID__".len()..];
+            continue
+        }
+        match quote {
+            Some(q) if c == q => quote = None,
+            None if c == '\'' || c == '"' || c == '`' => quote = Some(c),
+            _ => {}
+        }
+        out.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+
+    Some(out)
+}
+
+/// Replaces every `:name` placeholder outside of quoted text with what
+/// `replace` returns for its name, keeping it when that is `None`.
+fn replace_placeholders(query: &str, mut replace: impl FnMut(&str) -> Option<String>) -> String {
+    let chars = query.chars().collect::<Vec<_>>();
+    let mut out = String::with_capacity(query.len());
+    let mut quote = None;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '\'' || c == '"' || c == '`' => quote = Some(c),
+            None if c == ':' && chars.get(i + 1) == Some(&':') => {
+                out.push_str("::");
+                i += 2;
+                continue
+            }
+            None if c == ':' && chars.get(i + 1).is_some_and(|n| n.is_ascii_alphabetic() || *n == '_') => {
+                let end = (i + 1..chars.len()).find(|&j| !(chars[j].is_ascii_alphanumeric() || chars[j] == '_')).unwrap_or(chars.len());
+                let name = chars[i + 1..end].iter().collect::<String>();
+                if let Some(r) = replace(&name) {
+                    out.push_str(&r);
+                    i = end;
+                    continue
+                }
+            }
+            None => {}
+        }
+        out.push(c);
+        i += 1;
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use crate::storage::connection::{bind_id, placeholders, positional, Param};
+
+    #[test]
+    fn positional_params() {
+        let params = vec![
+            (String::from("tenant"), Param::String(String::from("acme"))),
+            (String::from("id"), Param::Integer(7)),
+        ];
+        let (query, values) = positional(
+            "select ':tenant', x::text from t where tenant = :tenant and id = :id and y = :other or z = :tenant",
+            &params,
+            |i| format!("${}", i),
+        );
+        assert_eq!(query, "select ':tenant', x::text from t where tenant = $1 and id = $2 and y = :other or z = $3");
+        assert_eq!(values, vec![params[0].1.clone(), params[1].1.clone(), params[0].1.clone()]);
+        assert_eq!(placeholders("select ':a' from t where b = :b and c::text = :c or d = :b"), vec!["b", "c"]);
+    }
+
+    #[test]
+    fn bind_id_placeholder() {
+        assert_eq!(bind_id("select * from t where id = '__PID__' or parent = __PID__").as_deref(), Some("select * from t where id = :__PID__ or parent = :__PID__"));
+        assert_eq!(bind_id("select '__PID__ ' from t").as_deref(), None);
+        assert_eq!(bind_id("select * from t where name like '%__PID__%'"), None);
+        assert_eq!(bind_id("select * from t").as_deref(), Some("select * from t"));
+    }
+}
@@ -1,30 +1,58 @@
-use std::cell::RefCell;
 use std::ops::Index;
 use mysql_async::prelude::Queryable;
-use mysql_async::Value;
+use std::error::Error;
+use mysql_async::{DriverError, Value};
 use crate::storage::connection;
-use crate::storage::connection::{Connection, Row};
+use crate::config::config::ErrorKind;
+use crate::storage::connection::{Connection, Param, Row};
 
 pub struct Client {
-    pool: mysql_async::Pool
+    pool: mysql_async::Pool,
+    pool_size: usize,
 }
 
 impl Client {
     pub fn new(url: String) -> Self {
         let pool = mysql_async::Pool::new(url.as_str());
+        let pool_size = mysql_async::Opts::from_url(url.as_str())
+            .map(|o| o.pool_opts().constraints().max())
+            .unwrap_or(1);
 
         Self {
             pool,
+            pool_size,
         }
     }
 }
 
 impl Connection for Client {
-    fn exec(&self, query: String) -> connection::ExecResult {
-        return Box::pin(
+    fn exec(&self, query: String, params: Vec<(String, Param)>) -> connection::ExecResult<'_> {
+        Box::pin(
             async move {
+                let (query, values) = connection::positional(&query, &params, |_| String::from("?"));
                 let mut conn = self.pool.get_conn().await?;
-                let rows: Vec<mysql_async::Row> = conn.query(query).await?;
+                // a dropped query keeps running on the server, kill it from another connection
+                let (pool, id) = (self.pool.clone(), conn.id());
+                let cancel = connection::CancelOnDrop::new(async move {
+                    let killed = match pool.get_conn().await {
+                        Ok(mut c) => c.query_drop(format!("KILL QUERY {}", id)).await,
+                        Err(e) => Err(e),
+                    };
+                    if let Err(e) = killed {
+                        log::warn!("failed to kill mysql query {}: {}", id, e);
+                    }
+                });
+                let rows: Result<Vec<mysql_async::Row>, Box<dyn Error>> = if values.is_empty() {
+                    conn.query(query).await.map_err(Into::into)
+                } else {
+                    match values.iter().map(to_value).collect::<Result<Vec<_>, _>>() {
+                        Ok(values) => conn.exec(query, values).await.map_err(Into::into),
+                        Err(e) => Err(e),
+                    }
+                };
+                // only a query still running is killed, a failed one is over
+                cancel.disarm();
+                let rows = rows?;
                 let mut result = vec![];
 
                 for row in rows {
@@ -38,6 +66,9 @@ impl Connection for Client {
                             Value::UInt(v) => v.to_string(),
                             Value::Float(v) => v.to_string(),
                             Value::Double(v) => v.to_string(),
+                            // prepared statements return dates and times in binary form
+                            Value::Date(y, m, d, 0, 0, 0, 0) => format!("{:04}-{:02}-{:02}", y, m, d),
+                            Value::Date(y, m, d, h, mi, s, _) => format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", y, m, d, h, mi, s),
                             _ => return Err("unsupported type".into())
                         };
 
@@ -50,8 +81,46 @@ impl Connection for Client {
 
                 Ok(result)
             }
-        );
+        )
+    }
+
+    fn ping(&self) -> connection::PingResult<'_> {
+        Box::pin(
+            async move {
+                let mut conn = self.pool.get_conn().await?;
+                conn.ping().await?;
+                Ok(())
+            }
+        )
     }
+
+    fn error_kind(&self, e: &(dyn Error + 'static)) -> ErrorKind {
+        match e.downcast_ref::<mysql_async::Error>() {
+            // ER_LOCK_DEADLOCK, ER_LOCK_WAIT_TIMEOUT
+            Some(mysql_async::Error::Server(se)) if se.code == 1213 || se.code == 1205 => ErrorKind::Deadlock,
+            // ER_CON_COUNT_ERROR
+            Some(mysql_async::Error::Server(se)) if se.code == 1040 => ErrorKind::PoolExhausted,
+            Some(mysql_async::Error::Io(_)) => ErrorKind::Connection,
+            Some(mysql_async::Error::Driver(DriverError::ConnectionClosed)) => ErrorKind::Connection,
+            Some(mysql_async::Error::Driver(DriverError::PoolDisconnected)) => ErrorKind::PoolExhausted,
+            _ => ErrorKind::Other,
+        }
+    }
+
+    fn pool_size(&self) -> usize {
+        self.pool_size
+    }
+}
+
+fn to_value(v: &Param) -> Result<Value, Box<dyn Error>> {
+    let r = match v {
+        Param::String(v) => Value::Bytes(v.as_bytes().to_vec()),
+        Param::Integer(v) => Value::Int(*v),
+        Param::Number(v) => Value::Double(v.parse()?),
+        Param::Boolean(v) => Value::Int(*v as i64),
+    };
+
+    Ok(r)
 }
 
 #[cfg(test)]
@@ -68,7 +137,7 @@ mod test {
         init_data();
         let client = Client::new(DB_URL.to_string());
 
-        let rows = client.exec("select id, name, flag from test".to_string()).await.unwrap();
+        let rows = client.exec("select id, name, flag from test".to_string(), vec![]).await.unwrap();
         assert_eq!(rows.len(), 1);
         assert_eq!(rows[0].columns.len(), 3);
         assert_eq!(rows[0].columns.iter().map(|x| x.0.to_owned()).collect::<Vec<_>>(), vec!["id", "name", "flag"]);
@@ -1,11 +1,10 @@
-use std::cell::RefCell;
 use std::error::Error;
-use std::future::Future;
-use futures_executor::block_on;
 use tokio::task::JoinHandle;
-use tokio_postgres::GenericClient;
+use tokio_postgres::error::SqlState;
+use tokio_postgres::types::{ToSql, Type};
+use crate::config::config::ErrorKind;
 use crate::storage::connection;
-use crate::storage::connection::{Connection, Row};
+use crate::storage::connection::{Connection, Param, Row};
 
 pub struct Client {
     client: tokio_postgres::Client,
@@ -40,16 +39,41 @@ impl Client {
 }
 
 impl Connection for Client {
-    fn exec(&self, query: String) -> connection::ExecResult {
-        return Box::pin(
+    fn exec(&self, query: String, params: Vec<(String, Param)>) -> connection::ExecResult<'_> {
+        Box::pin(
             async move {
-                let resp = self.client.query(&query, &[]).await?;
+                let (positional, values) = connection::positional(&query, &params, |i| format!("${}", i));
+                // a dropped query keeps running on the server and blocks the client's queue
+                let token = self.client.cancel_token();
+                let cancel = connection::CancelOnDrop::new(async move {
+                    if let Err(e) = token.cancel_query(tokio_postgres::NoTls).await {
+                        log::warn!("failed to cancel postgres query: {}", e);
+                    }
+                });
+                let resp = async {
+                    if values.is_empty() {
+                        return Ok(self.client.query(&positional, &[]).await?)
+                    }
+                    // bind every value as the type postgres inferred for its placeholder
+                    let mut stmt = self.client.prepare(&positional).await?;
+                    if let Some(cast) = text_casts(&query, &params, stmt.params()) {
+                        stmt = self.client.prepare(&cast).await?;
+                    }
+                    let args = stmt.params().iter().zip(values.iter())
+                        .map(|(ty, v)| to_sql(ty, v))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    let refs = args.iter().map(|a| a.as_ref() as &(dyn ToSql + Sync)).collect::<Vec<_>>();
+                    Ok::<_, Box<dyn Error>>(self.client.query(&stmt, &refs).await?)
+                }.await;
+                // only a query still running is cancelled, a failed one is over
+                cancel.disarm();
+                let resp = resp?;
                 let mut result = vec![];
 
                 for row in resp {
                     let mut columns = vec![];
                     for col in row.columns() {
-                        let value = parse_column_value(&row, &col)?;
+                        let value = parse_column_value(&row, col)?;
                         columns.push((col.name().to_string(), value));
                     }
                     result.push(Row{columns});
@@ -57,8 +81,67 @@ impl Connection for Client {
 
                 Ok(result)
             }
-        );
+        )
+    }
+
+    fn ping(&self) -> connection::PingResult<'_> {
+        Box::pin(
+            async move {
+                self.client.simple_query("select 1").await?;
+                Ok(())
+            }
+        )
+    }
+
+    fn error_kind(&self, e: &(dyn Error + 'static)) -> ErrorKind {
+        let e = match e.downcast_ref::<tokio_postgres::Error>() {
+            Some(e) => e,
+            None => return ErrorKind::Other,
+        };
+
+        match e.code() {
+            Some(c) if *c == SqlState::T_R_DEADLOCK_DETECTED || *c == SqlState::T_R_SERIALIZATION_FAILURE => ErrorKind::Deadlock,
+            Some(c) if *c == SqlState::TOO_MANY_CONNECTIONS => ErrorKind::PoolExhausted,
+            _ if e.is_closed() => ErrorKind::Connection,
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+/// Whether `to_sql` binds values of `ty` natively.
+fn native(ty: &Type) -> bool {
+    matches!(*ty, Type::INT2 | Type::INT4 | Type::INT8 | Type::FLOAT4 | Type::FLOAT8 | Type::BOOL | Type::TEXT | Type::VARCHAR | Type::BPCHAR | Type::NAME | Type::UNKNOWN)
+}
+
+/// Renders `query` with every placeholder of a type without a native binding,
+/// such as `date`, `timestamptz`, `numeric` or `uuid`, bound as text and cast to
+/// that type. `None` when every placeholder binds natively.
+fn text_casts(query: &str, params: &[(String, Param)], types: &[Type]) -> Option<String> {
+    if types.iter().all(native) {
+        return None
     }
+
+    let (query, _) = connection::positional(query, params, |i| match types.get(i - 1) {
+        Some(ty) if !native(ty) => format!("${}::text::\"{}\".\"{}\"", i, ty.schema(), ty.name()),
+        _ => format!("${}", i),
+    });
+    Some(query)
+}
+
+fn to_sql(ty: &Type, v: &Param) -> Result<Box<dyn ToSql + Sync + Send>, Box<dyn Error>> {
+    let text = v.to_text();
+    let r: Box<dyn ToSql + Sync + Send> = match *ty {
+        Type::INT2 => Box::new(text.parse::<i16>()?),
+        Type::INT4 => Box::new(text.parse::<i32>()?),
+        Type::INT8 => Box::new(text.parse::<i64>()?),
+        Type::FLOAT4 => Box::new(text.parse::<f32>()?),
+        Type::FLOAT8 => Box::new(text.parse::<f64>()?),
+        Type::BOOL => Box::new(text.parse::<bool>()?),
+        Type::TEXT | Type::VARCHAR | Type::BPCHAR | Type::NAME | Type::UNKNOWN => Box::new(text),
+        _ => return Err(format!("unsupported parameter type {}", ty.name()).into())
+    };
+
+    Ok(r)
 }
 
 fn parse_column_value(row: &tokio_postgres::Row, col: &tokio_postgres::Column) -> Result<String, Box<dyn Error>> {
@@ -79,10 +162,10 @@ fn parse_column_value(row: &tokio_postgres::Row, col: &tokio_postgres::Column) -
 
 #[cfg(test)]
 mod test {
-    use std::time::Duration;
     use postgres::NoTls;
-    use crate::storage::connection::Connection;
-    use crate::storage::db::postgres::Client;
+    use tokio_postgres::types::Type;
+    use crate::storage::connection::{Connection, Param};
+    use crate::storage::db::postgres::{text_casts, Client};
 
     const DB_URL: &str = "host=localhost port=15432 user=postgres password=postgres dbname=test";
 
@@ -92,26 +175,44 @@ mod test {
         init_data().await;
         let client = Client::new_async(DB_URL.to_string()).await;
 
-        let rows = client.exec("select id, name, flag from test".to_string()).await.unwrap();
+        let rows = client.exec("select id, name, flag from test".to_string(), vec![]).await.unwrap();
         assert_eq!(rows.len(), 1);
         assert_eq!(rows[0].columns.len(), 3);
         assert_eq!(rows[0].columns.iter().map(|x| x.0.to_owned()).collect::<Vec<_>>(), vec!["id", "name", "flag"]);
         assert_eq!(rows[0].columns.iter().map(|x| x.1.to_owned()).collect::<Vec<_>>(), vec!["1", "Islam", "true"]);
+
+        let params = vec![(String::from("as_of"), Param::String(String::from("2024-01-31")))];
+        let rows = client.exec("select id from test where created <= :as_of".to_string(), params).await.unwrap();
+        assert_eq!(rows.len(), 1);
+    }
+
+    #[test]
+    fn casts() {
+        let params = vec![
+            (String::from("as_of"), Param::String(String::from("2024-01-31"))),
+            (String::from("id"), Param::Integer(1)),
+        ];
+        let query = "select * from test where created <= :as_of and id = :id";
+        assert_eq!(
+            text_casts(query, &params, &[Type::DATE, Type::INT4]).unwrap(),
+            r#"select * from test where created <= $1::text::"pg_catalog"."date" and id = $2"#
+        );
+        assert_eq!(text_casts(query, &params, &[Type::TEXT, Type::INT4]), None);
     }
 
     async fn init_data() {
-        let (mut conn, conn2) = tokio_postgres::connect(DB_URL, NoTls).await.unwrap();
+        let (conn, conn2) = tokio_postgres::connect(DB_URL, NoTls).await.unwrap();
         tokio::spawn(async move{
             if let Err(e) = conn2.await {
                 panic!("{}", e);
             }
         });
-        conn.execute("create table test(id int PRIMARY KEY, name varchar, flag boolean)", &[]).await.unwrap();
-        conn.execute("insert into test (id, name, flag) values (1, 'Islam', true)", &[]).await.unwrap();
+        conn.execute("create table test(id int PRIMARY KEY, name varchar, flag boolean, created date)", &[]).await.unwrap();
+        conn.execute("insert into test (id, name, flag, created) values (1, 'Islam', true, '2024-01-01')", &[]).await.unwrap();
     }
 
     async fn drop_data() {
-        let (mut conn, conn2) = tokio_postgres::connect(DB_URL, NoTls).await.unwrap();
+        let (conn, conn2) = tokio_postgres::connect(DB_URL, NoTls).await.unwrap();
         tokio::spawn(async move{
             if let Err(e) = conn2.await {
                 panic!("{}", e);
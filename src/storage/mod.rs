@@ -1,3 +1,5 @@
+#[allow(clippy::module_inception)]
 pub mod storage;
 pub mod connection;
+pub mod breaker;
 pub mod db;
\ No newline at end of file
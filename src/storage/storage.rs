@@ -1,43 +1,162 @@
-use std::cell::{Ref, RefCell};
 use std::collections::HashMap;
 use std::error::Error;
 use std::sync::{Arc, RwLock};
-use crate::storage::connection::{Connection, ExecResult, Row};
-use crate::config::config::Connection as ConfigConnection;
+use std::time::{Duration, Instant};
+use futures_util::future::join_all;
+use crate::storage::connection::{Connection, Param, Row};
+use rand::Rng;
+use crate::config::config::{BreakerSettings, Connection as ConfigConnection, RetryPolicy};
+use crate::storage::breaker::CircuitBreaker;
+use crate::metrics::metrics::{metrics, POOL_IN_USE, POOL_MAX, QUERY_DURATION, QUERY_ERRORS, QUERY_RETRIES};
 
 pub struct Storage {
-    connections: RwLock<HashMap<ConfigConnection, Box<dyn Connection>>>
+    connections: RwLock<HashMap<ConfigConnection, Arc<dyn Connection>>>,
+    retry_policies: RwLock<HashMap<ConfigConnection, RetryPolicy>>,
+    breakers: RwLock<HashMap<ConfigConnection, Arc<CircuitBreaker>>>,
+}
+
+/// Keeps the in-use gauge right even when a query future is dropped by a timeout.
+struct InUse(&'static str);
+
+impl InUse {
+    fn new(conn: &'static str) -> Self {
+        metrics().add(POOL_IN_USE, &[("connection", conn)], 1);
+        Self(conn)
+    }
+}
+
+impl Drop for InUse {
+    fn drop(&mut self) {
+        metrics().add(POOL_IN_USE, &[("connection", self.0)], -1);
+    }
 }
 
 impl Storage {
     pub fn new() -> Self {
         Self {
             connections: RwLock::new(HashMap::new()),
+            retry_policies: RwLock::new(HashMap::new()),
+            breakers: RwLock::new(HashMap::new()),
         }
     }
 
+    pub fn set_circuit_breaker(&self, conn_t: ConfigConnection, settings: BreakerSettings) {
+        let mut mp = self.breakers.write().unwrap();
+        mp.insert(conn_t, Arc::new(CircuitBreaker::new(settings)));
+    }
+
+    /// State of the circuit of every connection that has a breaker.
+    pub fn circuit_states(&self) -> Vec<(ConfigConnection, &'static str)> {
+        self.breakers.read().unwrap().iter()
+            .map(|(k, v)| (k.clone(), v.state_name()))
+            .collect()
+    }
+
+    pub fn set_retry_policy(&self, conn_t: ConfigConnection, policy: RetryPolicy) {
+        let mut mp = self.retry_policies.write().unwrap();
+        mp.insert(conn_t, policy);
+    }
+
     pub fn add_connection(&self, conn_t: ConfigConnection, conn: Box<dyn Connection>) {
+        metrics().set(POOL_MAX, &[("connection", conn_t.name())], conn.pool_size() as i64);
+        metrics().set(POOL_IN_USE, &[("connection", conn_t.name())], 0);
+
         let mut mp = self.connections.write().unwrap();
-        mp.insert(conn_t, conn);
+        mp.insert(conn_t, Arc::from(conn));
     }
 
-    pub async fn exec(&self, conn_t: ConfigConnection, query: String) -> Result<Vec<Row>, Box<dyn Error>> {
-        let mp = self.connections.read().unwrap();
-        let conn: &Box<dyn Connection> = mp.get(&conn_t).unwrap();
-        conn.exec(query).await
+    /// Runs `query` with `params` bound on the connection, retrying transient errors according to
+    /// `retry` or, when it is `None`, the policy registered for the connection.
+    /// Fails with `CircuitOpen` while the connection's circuit is open.
+    pub async fn exec(&self, conn_t: ConfigConnection, query: String, params: Vec<(String, Param)>, retry: Option<&RetryPolicy>) -> Result<Vec<Row>, Box<dyn Error>> {
+        let conn = self.connections.read().unwrap().get(&conn_t).cloned()
+            .ok_or(format!("connection {:?} is not registered", conn_t))?;
+
+        let policy = match retry {
+            Some(p) => p.clone(),
+            None => self.retry_policies.read().unwrap().get(&conn_t).cloned().unwrap_or(RetryPolicy::new()),
+        };
+
+        let breaker = self.breakers.read().unwrap().get(&conn_t).cloned();
+
+        let mut attempt = 1;
+        loop {
+            let permit = match &breaker {
+                Some(b) => Some(b.acquire()?),
+                None => None,
+            };
+
+            let res = self.exec_once(&conn_t, conn.as_ref(), query.clone(), params.clone()).await;
+            match (permit, &res) {
+                (Some(p), Ok(_)) => p.success(),
+                (Some(p), Err(_)) => p.failure(),
+                (None, _) => {}
+            }
+
+            let kind = match res {
+                Err(e) if attempt < policy.max_attempts => {
+                    let kind = conn.error_kind(e.as_ref());
+                    if !policy.retry_on.contains(&kind) {
+                        return Err(e)
+                    }
+                    kind
+                }
+                res => return res,
+            };
+
+            metrics().inc(QUERY_RETRIES, &[("connection", conn_t.name()), ("kind", kind.name())], 1);
+            tokio::time::sleep(backoff(&policy, attempt)).await;
+            attempt += 1;
+        }
     }
+
+    async fn exec_once(&self, conn_t: &ConfigConnection, conn: &dyn Connection, query: String, params: Vec<(String, Param)>) -> Result<Vec<Row>, Box<dyn Error>> {
+        let labels = [("connection", conn_t.name())];
+        let _in_use = InUse::new(conn_t.name());
+        let start = Instant::now();
+        let res = conn.exec(query, params).await;
+        metrics().observe(QUERY_DURATION, &labels, start.elapsed());
+
+        if res.is_err() {
+            metrics().inc(QUERY_ERRORS, &labels, 1);
+        }
+
+        res
+    }
+
+    /// Pings every registered connection, giving each one at most `timeout`.
+    pub async fn ping_all(&self, timeout: Duration) -> Vec<(ConfigConnection, Result<(), String>)> {
+        let conns = self.connections.read().unwrap().iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect::<Vec<_>>();
+
+        let futs = conns.into_iter().map(|(conn_t, conn)| async move {
+            let res = match tokio::time::timeout(timeout, conn.ping()).await {
+                Err(_) => Err(String::from("timed out")),
+                Ok(r) => r.map_err(|e| e.to_string()),
+            };
+            (conn_t, res)
+        });
+
+        join_all(futs).await
+    }
+}
+
+/// Exponential backoff capped at `max_backoff_ms`, with the upper half jittered.
+fn backoff(policy: &RetryPolicy, attempt: u32) -> Duration {
+    let exp = policy.backoff_ms.saturating_mul(1 << (attempt - 1).min(16)).min(policy.max_backoff_ms);
+    let half = exp / 2;
+    Duration::from_millis(half + rand::thread_rng().gen_range(0..=exp - half))
 }
 
 #[cfg(test)]
 mod test {
     use std::error::Error;
-    use futures::join;
-    use futures_executor::block_on;
-    use futures_util::future::err;
-    use futures_util::FutureExt;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::time::Duration;
+    use crate::config::config::{ErrorKind, RetryPolicy};
     use crate::config::config::Connection::{MySQL, PostgresSQL};
-    use crate::storage::connection::{Connection, ExecResult, Row};
-    use crate::storage::db;
+    use crate::storage::connection::{Connection, ExecResult, Param, PingResult};
     use crate::storage::storage::Storage;
 
     struct MockConnection;
@@ -45,13 +164,52 @@ mod test {
         pub fn new() -> Self {Self {}}
     }
     impl Connection for MockConnection {
-        fn exec(&self, query: String) -> ExecResult {
+        fn exec(&self, _query: String, _params: Vec<(String, Param)>) -> ExecResult<'_> {
             Box::pin(
                async move {
                    Ok(vec![])
                }
             )
         }
+
+        fn ping(&self) -> PingResult<'_> {
+            Box::pin(async move { Ok(()) })
+        }
+    }
+
+    struct HangingConnection;
+    impl Connection for HangingConnection {
+        fn exec(&self, _query: String, _params: Vec<(String, Param)>) -> ExecResult<'_> {
+            Box::pin(futures::future::pending())
+        }
+
+        fn ping(&self) -> PingResult<'_> {
+            Box::pin(futures::future::pending())
+        }
+    }
+
+    /// Fails with a connection error until `failures` attempts have been made.
+    struct FlakyConnection {
+        failures: u32,
+        attempts: AtomicU32,
+    }
+    impl Connection for FlakyConnection {
+        fn exec(&self, _query: String, _params: Vec<(String, Param)>) -> ExecResult<'_> {
+            Box::pin(async move {
+                if self.attempts.fetch_add(1, Ordering::SeqCst) < self.failures {
+                    return Err("connection reset".into())
+                }
+                Ok(vec![])
+            })
+        }
+
+        fn ping(&self) -> PingResult<'_> {
+            Box::pin(async move { Ok(()) })
+        }
+
+        fn error_kind(&self, e: &(dyn Error + 'static)) -> ErrorKind {
+            if e.to_string() == "connection reset" { ErrorKind::Connection } else { ErrorKind::Other }
+        }
     }
 
     #[tokio::test]
@@ -59,7 +217,40 @@ mod test {
         let storage = Storage::new();
         storage.add_connection(PostgresSQL, Box::new(MockConnection::new()));
 
-        let res = storage.exec(PostgresSQL, String::from("test query")).await;
+        let res = storage.exec(PostgresSQL, String::from("test query"), vec![], None).await;
         assert!(res.is_ok());
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn ping_all() {
+        let storage = Storage::new();
+        storage.add_connection(PostgresSQL, Box::new(MockConnection::new()));
+        storage.add_connection(MySQL, Box::new(HangingConnection));
+
+        let mut res = storage.ping_all(Duration::from_millis(10)).await;
+        res.sort_by_key(|(c, _)| c.name());
+
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[0], (MySQL, Err(String::from("timed out"))));
+        assert_eq!(res[1], (PostgresSQL, Ok(())));
+    }
+
+    #[tokio::test]
+    async fn exec_retry() {
+        let storage = Storage::new();
+        storage.add_connection(PostgresSQL, Box::new(FlakyConnection { failures: 2, attempts: AtomicU32::new(0) }));
+        storage.add_connection(MySQL, Box::new(FlakyConnection { failures: 2, attempts: AtomicU32::new(0) }));
+
+        let mut policy = RetryPolicy::new();
+        policy.max_attempts = 3;
+        policy.backoff_ms = 1;
+        storage.set_retry_policy(PostgresSQL, policy.clone());
+
+        assert!(storage.exec(PostgresSQL, String::from("test query"), vec![], None).await.is_ok());
+        assert!(storage.exec(MySQL, String::from("test query"), vec![], None).await.is_err());
+
+        policy.retry_on = vec![ErrorKind::Deadlock];
+        storage.add_connection(PostgresSQL, Box::new(FlakyConnection { failures: 1, attempts: AtomicU32::new(0) }));
+        assert!(storage.exec(PostgresSQL, String::from("test query"), vec![], Some(&policy)).await.is_err());
+    }
+}